@@ -0,0 +1,153 @@
+//! Year and month archive pages, like WordPress used to have (and old links still point at).
+//!
+//! Every post goes under the year and month of its date (in the configured `timezone`), and
+//! the `archives.php` template gets rendered for each: `YYYY/index.<extension>` for a year,
+//! `YYYY/MM/index.<extension>` for a month, and `archives/index.<extension>` listing them all.
+//! Pages for months that no longer have any posts get cleaned up.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use tera::{Context, Tera};
+
+use crate::{
+    blog_atom_url, cache::BuildCache, finish_page, Includes, Post, PublishError, Settings,
+};
+
+/// The (optional) template for the archive pages.
+const ARCHIVES_TEMPLATE: &str = "archives.php";
+/// Where the list of every year and month goes.
+const ARCHIVES_PATH: &str = "archives";
+
+#[derive(Clone, Debug, Serialize)]
+struct Month {
+    year: i32,
+    /// 1 for January...
+    month: u32,
+    /// "March"
+    name: String,
+    /// Relative to `{{url}}`, like `2024/03`.
+    path: String,
+    /// How many posts are in it.
+    count: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Year {
+    year: i32,
+    path: String,
+    count: usize,
+    /// Newest first.
+    months: Vec<Month>,
+}
+
+/// Write the archive pages for `posts` (which should be every post, see `get_corpus`).
+pub fn update_archives(
+    settings: &Settings,
+    posts: &[Post],
+    tera: &Tera,
+    cache: &mut BuildCache,
+) -> Result<(), PublishError> {
+    if !tera
+        .get_template_names()
+        .any(|name| name == ARCHIVES_TEMPLATE)
+    {
+        debug!("📅 No {} template, so no archive pages", ARCHIVES_TEMPLATE);
+        return Ok(());
+    }
+    let mut by_month: BTreeMap<(i32, u32), Vec<&Post>> = BTreeMap::new();
+    for post in posts {
+        by_month
+            .entry(settings.timezone.year_month(post.date))
+            .or_default()
+            .push(post);
+    }
+    let mut years: Vec<Year> = Vec::new();
+    for (&(year, month), month_posts) in by_month.iter().rev() {
+        let month = Month {
+            year,
+            month,
+            name: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|d| d.format("%B").to_string())
+                .unwrap_or_default(),
+            path: format!("{}/{:02}", year, month),
+            count: month_posts.len(),
+        };
+        match years.last_mut() {
+            Some(last) if last.year == year => {
+                last.count += month.count;
+                last.months.push(month);
+            }
+            _ => years.push(Year {
+                year,
+                path: year.to_string(),
+                count: month.count,
+                months: vec![month],
+            }),
+        }
+    }
+
+    let mut written = BTreeSet::new();
+    let mut render = |path: &str,
+                      title: &str,
+                      year: Option<&Year>,
+                      month: Option<&Month>,
+                      posts: &[&Post]|
+     -> Result<(), PublishError> {
+        let mut context = Context::new();
+        context.insert("url", &settings.url);
+        context.insert("blog_atom_url", &blog_atom_url(settings));
+        context.insert("php", &(settings.includes == Includes::Php));
+        context.insert("path", path);
+        context.insert("title", title);
+        context.insert("years", &years);
+        context.insert("year", &year);
+        context.insert("month", &month);
+        // Newest first, like the tag pages.
+        let newest_first: Vec<&&Post> = posts.iter().rev().collect();
+        context.insert("posts", &newest_first);
+        let dir = Path::new(&settings.output).join(path);
+        fs::create_dir_all(&dir)?;
+        let page = finish_page(settings, tera.render(ARCHIVES_TEMPLATE, &context)?)?;
+        fs::write(dir.join(format!("index.{}", settings.extension)), page)?;
+        written.insert(path.to_owned());
+        Ok(())
+    };
+    render(ARCHIVES_PATH, "Archives", None, None, &[])?;
+    for year in &years {
+        let year_posts: Vec<&Post> = by_month
+            .range((year.year, 0)..=(year.year, 12))
+            .flat_map(|(_, posts)| posts.iter().copied())
+            .collect();
+        render(&year.path, &year.path, Some(year), None, &year_posts)?;
+        for month in &year.months {
+            render(
+                &month.path,
+                &format!("{} {}", month.name, month.year),
+                Some(year),
+                Some(month),
+                &by_month[&(month.year, month.month)],
+            )?;
+        }
+    }
+    info!(
+        "📅 Generated archives for {} year(s), {} month(s)",
+        years.len(),
+        by_month.len()
+    );
+
+    // Months sort after their years, so going backwards empties the month directories first.
+    let stale: Vec<&String> = cache.archives.difference(&written).collect();
+    for stale in stale.into_iter().rev() {
+        info!("📅 Removing empty archive {:?}", stale);
+        let dir = Path::new(&settings.output).join(stale);
+        let _ = fs::remove_file(dir.join(format!("index.{}", settings.extension)));
+        let _ = fs::remove_dir(dir);
+    }
+    cache.archives = written;
+    Ok(())
+}
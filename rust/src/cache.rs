@@ -0,0 +1,398 @@
+//! A persistent build manifest so `publish` only re-renders Posts whose inputs changed.
+//!
+//! The manifest lives in the output directory and records, for every source file, the
+//! hash of the file, the hash of the templates (and the settings that end up in the
+//! rendered page) and the identity of its prev/next neighbours at the time it was written.
+//! It also keeps the header details (title, tags, date...) of every Post, so listings that
+//! need the whole corpus don't have to re-read every file.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    images::{self, CachedImage},
+    includes, template_dir, Includes, Post, PublishError, Settings,
+};
+
+/// Name of the manifest file written to the output directory.
+pub const MANIFEST: &str = ".publish_manifest.json";
+
+/// The layout of the manifest, and of the `Post`s cached in it. Bump this whenever either
+/// changes (like a new field on `Post`), or old manifests will quietly hand back defaults.
+const SCHEMA: u32 = 1;
+
+/// Everything that goes into rendering a single Post.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CacheKey {
+    /// Hash of the markdown source file
+    pub source: String,
+    /// Hash of the templates and render settings
+    pub template: String,
+    /// Identity of the previous Post (file name and source hash)
+    pub prev: Option<String>,
+    /// Identity of the next Post (file name and source hash)
+    pub next: Option<String>,
+    /// The related Posts (link and title)
+    #[serde(default)]
+    pub related: Vec<String>,
+    /// The original images the Post points at, and their hashes (see `images::fingerprints`)
+    #[serde(default)]
+    pub images: Vec<String>,
+}
+
+/// What we know about a previously rendered Post.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CacheEntry {
+    pub key: CacheKey,
+    /// The file that was written for this Post.
+    pub output: String,
+    /// The original images the Post points at.
+    #[serde(default)]
+    pub images: Vec<String>,
+}
+
+/// The header details of a Post, along with the hash of the source they came from.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CachedPost {
+    pub source: String,
+    pub post: Post,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BuildCache {
+    /// The version of `publish` that wrote the manifest. A different version invalidates it.
+    version: String,
+    /// The `SCHEMA` the manifest was written with. A different one invalidates it.
+    #[serde(default)]
+    schema: u32,
+    /// Entries keyed by the source file name.
+    entries: BTreeMap<String, CacheEntry>,
+    /// Hash of the settings that end up in a Post (like the links).
+    settings: String,
+    /// Post header details keyed by the source file name.
+    posts: BTreeMap<String, CachedPost>,
+    /// The tags we wrote category files for last time.
+    pub tags: BTreeSet<String>,
+    /// The year and month archive pages we wrote last time.
+    #[serde(default)]
+    pub archives: BTreeSet<String>,
+    /// The resized copies of the post images, keyed by the original's path.
+    #[serde(default)]
+    pub images: BTreeMap<String, CachedImage>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    force: bool,
+    #[serde(skip)]
+    template_hash: Option<String>,
+    #[serde(skip)]
+    source_hashes: HashMap<String, String>,
+}
+
+impl BuildCache {
+    /// Read the manifest from the output directory. A missing or unreadable manifest just
+    /// means everything gets rebuilt.
+    pub fn load(settings: &Settings) -> Result<Self, PublishError> {
+        let path = Path::new(&settings.output).join(MANIFEST);
+        let mut cache = match fs::read_to_string(&path) {
+            Ok(buffer) => serde_json::from_str::<BuildCache>(&buffer).unwrap_or_else(|e| {
+                warn!("🗃️ Ignoring unreadable build manifest {:?}: {:?}", &path, e);
+                BuildCache::default()
+            }),
+            Err(_) => BuildCache::default(),
+        };
+        if cache.version != env!("CARGO_PKG_VERSION") || cache.schema != SCHEMA || settings.force {
+            debug!("🗃️ Starting with an empty build manifest");
+            cache.entries.clear();
+            cache.posts.clear();
+        }
+        let settings_hash = hash_settings(settings);
+        if cache.settings != settings_hash {
+            cache.posts.clear();
+            cache.settings = settings_hash;
+        }
+        cache.version = env!("CARGO_PKG_VERSION").to_owned();
+        cache.schema = SCHEMA;
+        cache.path = path;
+        cache.force = settings.force;
+        Ok(cache)
+    }
+
+    /// Write the manifest back to the output directory.
+    pub fn save(&self) -> Result<(), PublishError> {
+        trace!("🗃️ Writing build manifest {:?}", &self.path);
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Build the key for the file at `index` in the (sorted) list of source `files`.
+    /// (The templates get hashed the first time this is called, since any included files
+    /// need to be written first.)
+    pub fn key(
+        &mut self,
+        settings: &Settings,
+        files: &[String],
+        index: usize,
+    ) -> Result<CacheKey, PublishError> {
+        if self.template_hash.is_none() {
+            self.template_hash = Some(hash_templates(settings)?);
+        }
+        let prev = match index.checked_sub(1) {
+            Some(i) => Some(self.identity(&files[i])?),
+            None => None,
+        };
+        let next = match files.get(index + 1) {
+            Some(file) => Some(self.identity(file)?),
+            None => None,
+        };
+        // Which images the Post uses can't change without the source changing, so the ones from
+        // last time will do.
+        let images = self
+            .entries
+            .get(&file_name(&files[index]))
+            .map(|entry| images::fingerprints(&entry.images))
+            .unwrap_or_default();
+        Ok(CacheKey {
+            source: self.source_hash(&files[index])?,
+            template: self.template_hash.clone().unwrap_or_default(),
+            prev,
+            next,
+            // Only the caller knows these.
+            related: Vec::new(),
+            images,
+        })
+    }
+
+    /// Is the previously rendered output for this file still good? Returns the output file if so.
+    pub fn is_fresh(&self, file: &str, key: &CacheKey) -> Option<&str> {
+        if self.force {
+            return None;
+        }
+        self.entries
+            .get(&file_name(file))
+            .filter(|entry| &entry.key == key && Path::new(&entry.output).exists())
+            .map(|entry| entry.output.as_str())
+    }
+
+    /// Has `file` been rendered before (and is the output still there)?
+    pub fn is_rendered(&self, file: &str) -> bool {
+        self.entries
+            .get(&file_name(file))
+            .is_some_and(|entry| Path::new(&entry.output).exists())
+    }
+
+    /// Record that `file` was rendered to `output` using `key`, and that it points at the
+    /// original `images`.
+    pub fn update(&mut self, file: &str, key: CacheKey, output: String, images: Vec<String>) {
+        self.entries.insert(
+            file_name(file),
+            CacheEntry {
+                key,
+                output,
+                images,
+            },
+        );
+    }
+
+    /// Drop any entries for source files that no longer exist.
+    pub fn retain(&mut self, files: &[String]) {
+        let names: BTreeSet<String> = files.iter().map(|f| file_name(f)).collect();
+        self.entries.retain(|name, _| names.contains(name));
+        self.posts.retain(|name, _| names.contains(name));
+    }
+
+    /// Get the header details for the Post in `file`, only reading the file if it changed.
+    /// The returned Post has no `md_body`, `body` or `toc`.
+    pub async fn post(&mut self, settings: &Settings, file: &str) -> Result<Post, PublishError> {
+        let source = self.source_hash(file)?;
+        let name = file_name(file);
+        if let Some(cached) = self.posts.get(&name).filter(|c| c.source == source) {
+            return Ok(cached.post.clone());
+        }
+        let post = Post {
+            md_body: String::new(),
+            body: String::new(),
+            toc: Vec::new(),
+            ..Post::from_file(settings, file.to_owned()).await?
+        };
+        self.posts.insert(
+            name,
+            CachedPost {
+                source,
+                post: post.clone(),
+            },
+        );
+        Ok(post)
+    }
+
+    fn identity(&mut self, file: &str) -> Result<String, PublishError> {
+        Ok(format!("{}#{}", file_name(file), self.source_hash(file)?))
+    }
+
+    fn source_hash(&mut self, file: &str) -> Result<String, PublishError> {
+        if let Some(hash) = self.source_hashes.get(file) {
+            return Ok(hash.clone());
+        }
+        let hash = format!("{:x}", Sha256::digest(fs::read(file)?));
+        self.source_hashes.insert(file.to_owned(), hash.clone());
+        Ok(hash)
+    }
+}
+
+fn file_name(file: &str) -> String {
+    Path::new(file)
+        .file_name()
+        .and_then(|v| v.to_str())
+        .unwrap_or(file)
+        .to_owned()
+}
+
+/// Hash the settings that are baked into a Post.
+fn hash_settings(settings: &Settings) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(settings.url.as_bytes());
+    hasher.update(settings.short_url.clone().unwrap_or_default().as_bytes());
+    hasher.update(settings.extension.as_bytes());
+    hasher.update(settings.atom.as_bytes());
+    hasher.update(format!("{:?}", settings.includes).as_bytes());
+    hasher.update(settings.timezone.to_string().as_bytes());
+    hasher.update(format!("{:?} {}", settings.image_widths, settings.webp).as_bytes());
+    hasher.update(format!("{:?} {}", settings.highlight, settings.highlight_theme).as_bytes());
+    hasher.update(format!("{:?}", settings.markdown).as_bytes());
+    hasher.update(settings.words_per_minute.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Hash every file in the template directory, along with the settings that end up in
+/// rendered pages, so changing either invalidates the rendered posts. If we're inlining
+/// `include()`s, the included files count as part of the template.
+fn hash_templates(settings: &Settings) -> Result<String, PublishError> {
+    let mut hasher = Sha256::new();
+    hasher.update(hash_settings(settings).as_bytes());
+    let mut templates: Vec<PathBuf> = fs::read_dir(template_dir(settings))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    templates.sort();
+    for template in templates {
+        hasher.update(template.file_name().unwrap_or_default().as_encoded_bytes());
+        let content = fs::read(&template)?;
+        if settings.includes == Includes::Inline {
+            for include in includes::referenced(settings, &String::from_utf8_lossy(&content)) {
+                hasher.update(fs::read(include)?);
+            }
+        }
+        hasher.update(content);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A blog with three posts and a template, somewhere out of the way.
+    fn blog() -> (PathBuf, Settings, Vec<String>) {
+        let root = std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple()));
+        for dir in ["templates", "source", "archive"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("templates/index.php"), "{{ post.body }}").unwrap();
+        let files: Vec<String> = (1..=3)
+            .map(|n| {
+                let file = root.join(format!("source/000{n}_post.md"));
+                fs::write(&file, format!("Post {n}")).unwrap();
+                file.to_string_lossy().into_owned()
+            })
+            .collect();
+        let settings = Settings {
+            templates: format!("{}/templates/", root.display()),
+            output: root.join("archive").to_string_lossy().into_owned(),
+            ..Settings::default()
+        };
+        (root, settings, files)
+    }
+
+    fn keys(settings: &Settings, files: &[String]) -> Vec<CacheKey> {
+        let mut cache = BuildCache::load(settings).unwrap();
+        (0..files.len())
+            .map(|i| cache.key(settings, files, i).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keys_follow_the_neighbours() {
+        let (root, settings, files) = blog();
+        let before = keys(&settings, &files);
+        assert_eq!(before[0].prev, None);
+        assert_eq!(before[2].next, None);
+        assert_eq!(before, keys(&settings, &files));
+
+        fs::write(&files[0], "Post 1, edited").unwrap();
+        let after = keys(&settings, &files);
+        assert_ne!(before[0].source, after[0].source);
+        assert_ne!(before[1].prev, after[1].prev);
+        assert_eq!(before[2], after[2]);
+
+        fs::write(root.join("templates/index.php"), "{{ post.title }}").unwrap();
+        let restyled = keys(&settings, &files);
+        assert!(restyled.iter().all(|key| key.template != after[0].template));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn fresh_until_forced() {
+        let (root, mut settings, files) = blog();
+        let output = root.join("archive/1.html").to_string_lossy().into_owned();
+        let mut cache = BuildCache::load(&settings).unwrap();
+        let key = cache.key(&settings, &files, 0).unwrap();
+        cache.update(&files[0], key.clone(), output.clone(), Vec::new());
+        // The output has to still be there.
+        assert_eq!(cache.is_fresh(&files[0], &key), None);
+        fs::write(&output, "Post 1").unwrap();
+        assert_eq!(cache.is_fresh(&files[0], &key), Some(output.as_str()));
+        cache.save().unwrap();
+
+        assert!(BuildCache::load(&settings).unwrap().is_rendered(&files[0]));
+        settings.force = true;
+        let forced = BuildCache::load(&settings).unwrap();
+        assert!(!forced.is_rendered(&files[0]));
+        assert_eq!(forced.is_fresh(&files[0], &key), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn old_posts_are_dropped() {
+        let (root, settings, files) = blog();
+        let mut cache = BuildCache::load(&settings).unwrap();
+        let source = cache.source_hash(&files[0]).unwrap();
+        cache.posts.insert(
+            file_name(&files[0]),
+            CachedPost {
+                source,
+                post: Post::default(),
+            },
+        );
+        cache.save().unwrap();
+        assert_eq!(BuildCache::load(&settings).unwrap().posts.len(), 1);
+
+        // Written back when `Post` looked different.
+        cache.schema = SCHEMA - 1;
+        cache.save().unwrap();
+        assert!(BuildCache::load(&settings).unwrap().posts.is_empty());
+
+        cache.schema = SCHEMA;
+        cache.save().unwrap();
+        let forced = Settings {
+            force: true,
+            ..settings.clone()
+        };
+        assert!(BuildCache::load(&forced).unwrap().posts.is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
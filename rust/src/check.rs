@@ -0,0 +1,273 @@
+//! `publish check`: look over every source file and complain about anything that would go
+//! wrong (or quietly come out weird) when publishing. Nothing gets written.
+//!
+//! Errors make it exit non-zero, so it can go in a pre-commit hook. Warnings are things that
+//! are probably fine, but you might want to know about (like a gap in the post numbers).
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    draft_dir, front_matter::Format, get_draft_files, post_name_regex, Post, PublishError, Settings,
+};
+
+/// Keeps count of what we've complained about. (Also used by `publish links`.)
+#[derive(Debug, Default)]
+pub struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    pub fn error(&mut self, message: impl Display) {
+        println!("❌ {}", message);
+        self.errors += 1;
+    }
+
+    pub fn warning(&mut self, message: impl Display) {
+        println!("⚠️ {}", message);
+        self.warnings += 1;
+    }
+
+    /// Print the totals, and fail if there were any errors.
+    pub fn finish(&self, checked: &str) -> Result<(), PublishError> {
+        println!(
+            "Checked {}: {} error(s), {} warning(s)",
+            checked, self.errors, self.warnings
+        );
+        if self.errors > 0 {
+            return Err(PublishError::General(format!(
+                "{} problem(s) found",
+                self.errors
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub async fn check(settings: &Settings) -> Result<(), PublishError> {
+    let mut report = Report::default();
+    let name_re = post_name_regex();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&settings.source)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "md") {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+        if !name_re.is_match(name) {
+            report.error(format!(
+                "{}: Doesn't look like NNNN_name.md, so it won't be published",
+                path.display()
+            ));
+            continue;
+        }
+        files.push(path);
+    }
+    files.sort();
+    files.extend(get_draft_files(settings).iter().map(PathBuf::from));
+
+    let mut numbers: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for path in &files {
+        let file = path.to_string_lossy().into_owned();
+        if let Some(post) = check_file(settings, &file, &mut report).await {
+            if path.parent() != Some(&draft_dir(settings)) {
+                numbers.entry(post.num).or_default().push(file);
+            }
+        }
+    }
+
+    for (num, files) in &numbers {
+        if files.len() > 1 {
+            report.error(format!(
+                "Post number {:04} is used by {}",
+                num,
+                files.join(", ")
+            ));
+        }
+    }
+    let nums: Vec<u64> = numbers.keys().copied().collect();
+    for pair in nums.windows(2) {
+        match pair[1] - pair[0] {
+            1 => {}
+            2 => report.warning(format!("There's no post {:04}", pair[0] + 1)),
+            _ => report.warning(format!(
+                "There are no posts {:04} through {:04}",
+                pair[0] + 1,
+                pair[1] - 1
+            )),
+        }
+    }
+
+    report.finish(&format!("{} file(s)", files.len()))
+}
+
+/// Check a single file, returning the Post if it could be read at all.
+async fn check_file(settings: &Settings, file: &str, report: &mut Report) -> Option<Post> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            report.error(format!("{}: Could not read: {}", file, e));
+            return None;
+        }
+    };
+    let legacy = content
+        .lines()
+        .find(|line| !line.is_empty())
+        .is_none_or(|line| Format::from_fence(line).is_none());
+    if legacy {
+        check_legacy_header(file, &content, report);
+    }
+    let post = match Post::from_file(settings, file.to_owned()).await {
+        Ok(post) => post,
+        // These already say which file (and usually which line).
+        Err(PublishError::PostProcessError(message)) => {
+            report.error(message);
+            return None;
+        }
+        Err(e) => {
+            report.error(format!("{}: {}", file, e));
+            return None;
+        }
+    };
+    if post.title.trim().is_empty() {
+        report.error(format!("{}: Missing a title", file));
+    }
+    if post.md_body.trim().is_empty() && (!legacy || has_separator(&content)) {
+        report.error(format!("{}: The body is empty", file));
+    }
+    if post.timestamp == file_created(file) {
+        report.warning(format!(
+            "{}: No date, so it'll use the file's creation time",
+            file
+        ));
+    }
+    Some(post)
+}
+
+/// The old style header is matched line by line, so look for things it would quietly skip.
+fn check_legacy_header(file: &str, content: &str, report: &mut Report) {
+    if !has_separator(content) {
+        report.error(format!(
+            "{}: No \"===\" after the header, so the whole file is header",
+            file
+        ));
+    }
+    for (i, line) in content.lines().enumerate() {
+        if line.starts_with("===") {
+            break;
+        }
+        let at = format!("{}:{}", file, i + 1);
+        if line.starts_with('[') {
+            let inner = line
+                .trim_end()
+                .strip_suffix(']')
+                .unwrap_or_default()
+                .trim_start_matches('[');
+            let tags: Vec<&str> = inner
+                .split(',')
+                .filter(|_| !inner.trim().is_empty())
+                .map(|tag| tag.trim().trim_matches('"').trim())
+                .collect();
+            if !line.trim_end().ends_with(']') || line.matches('"').count() % 2 != 0 {
+                report.error(format!("{}: Malformed tag line {:?}", at, line));
+            } else if tags.iter().any(|tag| tag.is_empty()) {
+                report.error(format!("{}: Empty tag in {:?}", at, line));
+            }
+        } else if !(line.is_empty()
+            || line.starts_with("# ")
+            || line.starts_with("<!-- Date:")
+            || line.starts_with("> ")
+            || line.to_lowercase().starts_with("draft:"))
+        {
+            report.warning(format!("{}: Ignoring header line {:?}", at, line));
+        }
+    }
+}
+
+fn has_separator(content: &str) -> bool {
+    content.lines().any(|line| line.starts_with("==="))
+}
+
+/// `Post::from_file` falls back to this when there's no date.
+fn file_created(file: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(Path::new(file)).and_then(|m| m.created()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_all_files;
+
+    /// A source directory with `files` in it.
+    fn source(files: &[(&str, &str)]) -> (PathBuf, Settings) {
+        let root = std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&root).unwrap();
+        for (name, content) in files {
+            fs::write(root.join(name), content).unwrap();
+        }
+        let settings = Settings {
+            source: root.to_string_lossy().into_owned(),
+            ..Settings::default()
+        };
+        (root, settings)
+    }
+
+    const GOOD: &str = "# A post\n<!-- Date: 2024-10-21 08:00:00 -->\n===\nSome words.\n";
+
+    #[test]
+    fn names() {
+        let re = post_name_regex();
+        assert!(re.is_match("0012_foo.md"));
+        assert!(re.is_match("10000_more.md"));
+        assert!(!re.is_match("0012-foo.md"));
+        assert!(!re.is_match("012_foo.md"));
+        assert!(!re.is_match("0012_foo.txt"));
+    }
+
+    #[tokio::test]
+    async fn published_means_checked() {
+        let (root, settings) = source(&[
+            ("0001_first.md", GOOD),
+            ("0002-second.md", GOOD),
+            ("10000_way_later.md", GOOD),
+        ]);
+        let published = get_all_files(&settings).unwrap();
+        assert_eq!(published.len(), 2);
+        assert!(published[1].ends_with("10000_way_later.md"));
+        // The one that isn't getting published is the only problem.
+        assert!(check(&settings).await.is_err());
+        fs::remove_file(root.join("0002-second.md")).unwrap();
+        assert!(check(&settings).await.is_ok());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bad_posts() {
+        let (root, settings) = source(&[
+            ("0001_good.md", GOOD),
+            ("0002_untitled.md", "[\"tag\"]\n\nSome words.\n"),
+            ("0003_empty.md", "# Empty\n<!-- Date: 2024-10-21 -->\n===\n"),
+        ]);
+        let count = |name: &str| {
+            let settings = settings.clone();
+            let file = root.join(name).to_string_lossy().into_owned();
+            async move {
+                let mut report = Report::default();
+                check_file(&settings, &file, &mut report).await;
+                (report.errors, report.warnings)
+            }
+        };
+        assert_eq!(count("0001_good.md").await, (0, 0));
+        // No title and no `===` (so the body looks like a header line), and no date.
+        assert_eq!(count("0002_untitled.md").await, (2, 2));
+        assert_eq!(count("0003_empty.md").await, (1, 0));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+//! Reading the dates in post headers.
+//!
+//! Over the years the posts have picked up a few different ways of writing a date, so take
+//! whatever's reasonable: RFC 2822 (`Mon, 21 Oct 2024 08:00:00 -0700`, what the old exporter
+//! wrote), RFC 3339 / ISO 8601 with or without an offset (`2024-10-21T08:00:00-07:00`), and
+//! the `2024-10-21 08:00:00` the README has always shown. Anything without an offset is in the
+//! configured `timezone`.
+use std::{fmt, str::FromStr, time::SystemTime};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde_derive::Deserialize;
+
+/// Offset-less dates, in the order we try them.
+const NAIVE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Dates with an offset that RFC 3339 is too strict for.
+const OFFSET_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%dT%H:%M:%S%.f %z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+/// The timezone for dates that don't say. Either `local` (whatever this machine thinks) or
+/// an IANA name like `America/Los_Angeles`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Zone {
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Ok(Self::Local),
+            s if s.eq_ignore_ascii_case("local") => Ok(Self::Local),
+            s => Tz::from_str(s)
+                .map(Self::Named)
+                .map_err(|_| format!("Unknown timezone {:?}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl Zone {
+    /// Pin a date without an offset to this zone. (If the clocks went back, take the first
+    /// one. If they went forward over it, there's no such time.)
+    fn resolve(&self, naive: NaiveDateTime) -> Option<SystemTime> {
+        match self {
+            Self::Local => Local.from_local_datetime(&naive).earliest().map(Into::into),
+            Self::Named(tz) => tz.from_local_datetime(&naive).earliest().map(Into::into),
+        }
+    }
+
+    /// The year and month that `date` (seconds since the epoch) falls in, here.
+    pub fn year_month(&self, date: u64) -> (i32, u32) {
+        let utc = DateTime::from_timestamp(date as i64, 0).unwrap_or_default();
+        match self {
+            Self::Local => {
+                let local = utc.with_timezone(&Local);
+                (local.year(), local.month())
+            }
+            Self::Named(tz) => {
+                let local = utc.with_timezone(tz);
+                (local.year(), local.month())
+            }
+        }
+    }
+
+    /// Write out `time` as it'd read on a clock here (see `chrono::format::strftime`).
+    pub fn format(&self, time: SystemTime, format: &str) -> String {
+        match self {
+            Self::Local => DateTime::<Local>::from(time).format(format).to_string(),
+            Self::Named(tz) => DateTime::<Utc>::from(time)
+                .with_timezone(tz)
+                .format(format)
+                .to_string(),
+        }
+    }
+}
+
+/// Turn a header date into a time, or `None` if it's not something we understand.
+pub fn parse(date: &str, zone: Zone) -> Option<SystemTime> {
+    let date = date.trim();
+    if let Ok(parsed) = DateTime::parse_from_rfc2822(date) {
+        return Some(parsed.into());
+    }
+    // chrono (rightly) won't take a day of the week that doesn't match the date, but plenty
+    // of the old posts have one. The date is what we care about, so drop it and try again.
+    if let Some((day, rest)) = date.split_once(',') {
+        if day.trim().chars().all(|c| c.is_ascii_alphabetic()) {
+            if let Ok(parsed) = DateTime::parse_from_rfc2822(rest.trim()) {
+                warn!("📅 Ignoring the wrong day of the week in {:?}", date);
+                return Some(parsed.into());
+            }
+        }
+    }
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+        return Some(parsed.into());
+    }
+    for format in OFFSET_FORMATS {
+        if let Ok(parsed) = DateTime::parse_from_str(date, format) {
+            return Some(parsed.into());
+        }
+    }
+    for format in NAIVE_FORMATS {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(date, format) {
+            return zone.resolve(naive);
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .and_then(|naive| zone.resolve(naive))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    const LA: Zone = Zone::Named(chrono_tz::America::Los_Angeles);
+    /// 2024-10-21 15:00:00 UTC (08:00 in Los Angeles).
+    const MORNING: u64 = 1_729_522_800;
+
+    fn secs(date: &str) -> Option<u64> {
+        parse(date, LA).map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_secs())
+    }
+
+    #[test]
+    fn rfc2822() {
+        assert_eq!(secs("Mon, 21 Oct 2024 08:00:00 -0700"), Some(MORNING));
+        assert_eq!(secs("  Mon, 21 Oct 2024 15:00:00 +0000  "), Some(MORNING));
+        assert_eq!(secs("21 Oct 2024 08:00:00 -0700"), Some(MORNING));
+    }
+
+    #[test]
+    fn rfc2822_wrong_weekday() {
+        assert_eq!(secs("Fri, 21 Oct 2024 08:00:00 -0700"), Some(MORNING));
+        // Still has to be a date, though.
+        assert_eq!(secs("Fri, 32 Oct 2024 08:00:00 -0700"), None);
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(secs("2024-10-21T08:00:00-07:00"), Some(MORNING));
+        assert_eq!(secs("2024-10-21T15:00:00Z"), Some(MORNING));
+        assert_eq!(secs("2024-10-21T15:00:00.250Z"), Some(MORNING));
+        assert_eq!(secs("2024-10-21 08:00:00 -0700"), Some(MORNING));
+        assert_eq!(secs("2024-10-21T08:00:00-0700"), Some(MORNING));
+    }
+
+    #[test]
+    fn without_an_offset() {
+        assert_eq!(secs("2024-10-21 08:00:00"), Some(MORNING));
+        assert_eq!(secs("2024-10-21T08:00:00"), Some(MORNING));
+        assert_eq!(secs("2024-10-21 08:00"), Some(MORNING));
+        // Midnight, in Los Angeles.
+        assert_eq!(secs("2024-10-21"), Some(MORNING - 8 * 3600));
+        assert_eq!(
+            parse("2024-10-21 15:00:00", Zone::Named(chrono_tz::UTC)),
+            parse("2024-10-21 08:00:00", LA)
+        );
+    }
+
+    #[test]
+    fn daylight_saving() {
+        // The clocks went from 2:00 to 3:00, so there wasn't a 2:30.
+        assert_eq!(secs("2024-03-10 02:30:00"), None);
+        // They went from 2:00 back to 1:00, so there were two 1:30s. The first one wins.
+        assert_eq!(secs("2024-11-03 01:30:00"), Some(1_730_622_600));
+    }
+
+    #[test]
+    fn nonsense() {
+        assert_eq!(secs(""), None);
+        assert_eq!(secs("last Tuesday"), None);
+        assert_eq!(secs("2024-13-01"), None);
+    }
+
+    #[test]
+    fn zones() {
+        assert_eq!("local".parse::<Zone>(), Ok(Zone::Local));
+        assert_eq!("".parse::<Zone>(), Ok(Zone::Local));
+        assert_eq!("America/Los_Angeles".parse::<Zone>(), Ok(LA));
+        assert!("Mars/Olympus_Mons".parse::<Zone>().is_err());
+        assert_eq!(LA.to_string(), "America/Los_Angeles");
+    }
+
+    #[test]
+    fn year_month() {
+        // 2024-11-01 03:00 UTC is still Halloween in Los Angeles.
+        assert_eq!(LA.year_month(1_730_430_000), (2024, 10));
+        assert_eq!(
+            Zone::Named(chrono_tz::UTC).year_month(1_730_430_000),
+            (2024, 11)
+        );
+    }
+
+    #[test]
+    fn format() {
+        let morning = UNIX_EPOCH + std::time::Duration::from_secs(MORNING);
+        assert_eq!(LA.format(morning, "%Y-%m-%d %H:%M"), "2024-10-21 08:00");
+        assert_eq!(
+            Zone::Named(chrono_tz::UTC).format(morning, "%Y-%m-%d %H:%M"),
+            "2024-10-21 15:00"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Structured post headers.
+//!
+//! The original header format (`# Title`, `["tags"]`, `<!-- Date: ... -->`, `> summary`, then
+//! `===`) still works, but it's matched line by line, so it gets confused by things like a title
+//! that starts with a `[` or a summary that wanders over a few lines. A post can instead start
+//! with a block of TOML between `+++` lines, or YAML between `---` lines:
+//!
+//! ```text
+//! +++
+//! title = "[Citation needed]"
+//! tags = ["meta", "wikipedia"]
+//! date = 2025-01-13T09:30:00-08:00
+//! summary = """
+//! It's a long story.
+//! """
+//! +++
+//! The body starts here.
+//! ```
+use serde_derive::Deserialize;
+
+use crate::{dates::Zone, Post, PublishError};
+
+/// Which flavor of front matter we're reading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Does this line open (or close) a front matter block?
+    pub fn from_fence(line: &str) -> Option<Self> {
+        match line.trim_end() {
+            "+++" => Some(Self::Toml),
+            "---" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn fence(&self) -> &'static str {
+        match self {
+            Self::Toml => "+++",
+            Self::Yaml => "---",
+        }
+    }
+}
+
+/// Tags can be a proper list, or just a comma separated string.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Tags {
+    List(Vec<String>),
+    Line(String),
+}
+
+/// TOML has real dates, everything else is a string.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Date {
+    Text(String),
+    Toml(toml::value::Datetime),
+}
+
+/// The bits of a `Post` that can be set from the front matter. Anything not listed here gets
+/// ignored, so feel free to keep your own notes in there.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    title: Option<String>,
+    tags: Option<Tags>,
+    date: Option<Date>,
+    summary: Option<String>,
+    draft: Option<bool>,
+    /// Where the date was (`file:line`), for complaining about it.
+    #[serde(skip)]
+    date_at: String,
+}
+
+impl FrontMatter {
+    /// Parse the `lines` of the block (without the fences). `fence` is the line number of the
+    /// opening fence.
+    pub fn parse(
+        format: Format,
+        lines: &[String],
+        filepath: &str,
+        fence: usize,
+    ) -> Result<Self, PublishError> {
+        trace!("📜 {:?} front matter in {:?}", format, filepath);
+        let content = lines.join("\n");
+        let mut result: Self = match format {
+            Format::Toml => toml::from_str(&content).map_err(|e| {
+                PublishError::PostProcessError(format!("Bad TOML front matter in {filepath}: {e}"))
+            }),
+            // An empty YAML document is `null`, which isn't much of a struct.
+            Format::Yaml if content.trim().is_empty() => Ok(Self::default()),
+            Format::Yaml => serde_yaml::from_str(&content).map_err(|e| {
+                PublishError::PostProcessError(format!("Bad YAML front matter in {filepath}: {e}"))
+            }),
+        }?;
+        // Good enough for pointing at the right line.
+        let line = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with("date"))
+            .map_or(fence, |i| fence + 1 + i);
+        result.date_at = format!("{filepath}:{line}");
+        Ok(result)
+    }
+
+    /// Copy what we found over to the post.
+    pub fn apply(self, post: &mut Post, zone: Zone) -> Result<(), PublishError> {
+        if let Some(title) = self.title {
+            post.title = title.trim().to_owned();
+        }
+        match self.tags {
+            Some(Tags::List(tags)) => {
+                post.tags = tags.into_iter().map(|v| v.trim().to_owned()).collect();
+            }
+            Some(Tags::Line(line)) => {
+                post.parse_tags(&line)?;
+            }
+            None => {}
+        }
+        match self.date {
+            Some(Date::Text(date)) => post.set_timestamp(&date, zone, &self.date_at)?,
+            Some(Date::Toml(date)) => post.set_timestamp(&date.to_string(), zone, &self.date_at)?,
+            None => {}
+        }
+        if let Some(summary) = self.summary {
+            post.summary = Some(summary.trim().to_owned()).filter(|v| !v.is_empty());
+        }
+        if let Some(draft) = self.draft {
+            post.draft = draft;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    const LA: Zone = Zone::Named(chrono_tz::America::Los_Angeles);
+
+    fn read(format: Format, block: &str) -> Result<Post, PublishError> {
+        let lines: Vec<String> = block.lines().map(str::to_owned).collect();
+        let mut post = Post::default();
+        FrontMatter::parse(format, &lines, "0001_test.md", 1)?.apply(&mut post, LA)?;
+        Ok(post)
+    }
+
+    #[test]
+    fn fences() {
+        assert_eq!(Format::from_fence("+++"), Some(Format::Toml));
+        assert_eq!(Format::from_fence("---  "), Some(Format::Yaml));
+        assert_eq!(Format::from_fence("===="), None);
+        assert_eq!(Format::Yaml.fence(), "---");
+    }
+
+    #[test]
+    fn toml() {
+        let post = read(
+            Format::Toml,
+            r#"title = "[Citation needed]"
+tags = ["meta", " wikipedia "]
+date = 2025-01-13T09:30:00-08:00
+summary = """
+It's a long story.
+"""
+mood = "ignored""#,
+        )
+        .unwrap();
+        assert_eq!(post.title, "[Citation needed]");
+        assert_eq!(post.tags, ["meta", "wikipedia"]);
+        assert_eq!(
+            post.timestamp,
+            Some(UNIX_EPOCH + Duration::from_secs(1_736_789_400))
+        );
+        assert_eq!(post.summary.as_deref(), Some("It's a long story."));
+        assert!(!post.draft);
+    }
+
+    #[test]
+    fn toml_local_date() {
+        // A TOML date without an offset is in the configured timezone, like any other.
+        let post = read(Format::Toml, "date = 2025-01-13T09:30:00").unwrap();
+        assert_eq!(
+            post.timestamp,
+            Some(UNIX_EPOCH + Duration::from_secs(1_736_789_400))
+        );
+    }
+
+    #[test]
+    fn yaml() {
+        let post = read(
+            Format::Yaml,
+            "title: Plain old YAML\ntags: meta, wikipedia\ndate: 2025-01-13 09:30:00\ndraft: true\nsummary: \"  \"",
+        )
+        .unwrap();
+        assert_eq!(post.title, "Plain old YAML");
+        assert_eq!(post.tags, ["meta", "wikipedia"]);
+        assert_eq!(
+            post.timestamp,
+            Some(UNIX_EPOCH + Duration::from_secs(1_736_789_400))
+        );
+        assert_eq!(post.summary, None);
+        assert!(post.draft);
+    }
+
+    #[test]
+    fn empty() {
+        let post = read(Format::Yaml, "").unwrap();
+        assert_eq!(post.title, "");
+        assert!(post.tags.is_empty());
+        assert_eq!(post.timestamp, None);
+        read(Format::Toml, "").unwrap();
+    }
+
+    #[test]
+    fn bad() {
+        assert!(read(Format::Toml, "title = ").is_err());
+        assert!(read(Format::Yaml, "title: [oops").is_err());
+        // The complaint says where the date was.
+        let err = read(Format::Yaml, "title: Hi\n\ndate: someday").unwrap_err();
+        assert!(format!("{:?}", err).contains("0001_test.md:4"), "{:?}", err);
+    }
+}
@@ -0,0 +1,159 @@
+//! Syntax highlighting for fenced code blocks, done when building so the pages don't need any
+//! JavaScript.
+//!
+//! The language comes from the fence (` ```rust `). With `highlight = "classes"` (the
+//! default) the code gets `hl-` prefixed classes, and the colours live in a `highlight.css`
+//! written to the output directory from `highlight_theme`. With `highlight = "inline"` the
+//! colours go right on the elements, which is handy if you can't add a stylesheet. Blocks
+//! with no language, or one we don't know, are left as plain code.
+use std::{fs, path::Path, sync::OnceLock};
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use serde_derive::Deserialize;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{
+        css_for_theme_with_class_style, highlighted_html_for_string, ClassStyle,
+        ClassedHTMLGenerator,
+    },
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::{PublishError, Settings};
+
+/// The stylesheet written for `highlight = "classes"`.
+pub const HIGHLIGHT_CSS: &str = "highlight.css";
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// How (or if) to highlight the code blocks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlight {
+    /// Mark things up with classes, and write a stylesheet for them.
+    #[default]
+    Classes,
+    /// Put the colours right on the elements.
+    Inline,
+    /// Leave the code alone.
+    Off,
+}
+
+/// Loading these takes a moment, so only do it once.
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Look up the configured theme.
+pub fn theme(settings: &Settings) -> Result<&'static Theme, PublishError> {
+    themes()
+        .themes
+        .get(&settings.highlight_theme)
+        .ok_or_else(|| {
+            PublishError::SettingsError(format!(
+                "Unknown highlight_theme {:?}, try one of {:?}",
+                settings.highlight_theme,
+                themes().themes.keys().collect::<Vec<_>>()
+            ))
+        })
+}
+
+/// Write the stylesheet for the classes, if we're using them.
+pub fn write_css(settings: &Settings) -> Result<(), PublishError> {
+    if settings.highlight != Highlight::Classes {
+        return Ok(());
+    }
+    let css = css_for_theme_with_class_style(theme(settings)?, CLASS_STYLE)
+        .map_err(|e| PublishError::General(format!("Could not write highlight CSS {:?}", e)))?;
+    let path = Path::new(&settings.output).join(HIGHLIGHT_CSS);
+    trace!("🖍️ Writing {:?}", &path);
+    fs::write(path, css)?;
+    Ok(())
+}
+
+/// Swap any fenced code blocks in `events` for highlighted HTML.
+pub fn highlight<'a>(
+    settings: &Settings,
+    events: impl Iterator<Item = Event<'a>>,
+) -> Result<Vec<Event<'a>>, PublishError> {
+    let mut result = Vec::new();
+    // The language and what we've collected so far, while in a block we can highlight.
+    let mut block: Option<(&SyntaxReference, String, String)> = None;
+    for event in events {
+        match (event, &mut block) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), None)
+                if settings.highlight != Highlight::Off =>
+            {
+                match language(&info) {
+                    Some((lang, syntax)) => block = Some((syntax, lang, String::new())),
+                    None => result.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))),
+                }
+            }
+            (Event::Text(text), Some((_, _, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some((syntax, lang, code))) => {
+                let html = render(settings, syntax, lang, code)?;
+                result.push(Event::Html(CowStr::from(html)));
+                block = None;
+            }
+            (event, _) => result.push(event),
+        }
+    }
+    Ok(result)
+}
+
+/// Find the syntax for a fence's info string (which can have more than the language in it,
+/// like ` ```rust,ignore `).
+fn language(info: &str) -> Option<(String, &'static SyntaxReference)> {
+    let lang = info
+        .split([',', ' ', '{'])
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if lang.is_empty() {
+        return None;
+    }
+    let syntax = syntaxes().find_syntax_by_token(lang);
+    if syntax.is_none() {
+        debug!("🖍️ Don't know how to highlight {:?}", lang);
+    }
+    Some((lang.to_owned(), syntax?))
+}
+
+fn render(
+    settings: &Settings,
+    syntax: &SyntaxReference,
+    lang: &str,
+    code: &str,
+) -> Result<String, PublishError> {
+    let failed = |e: syntect::Error| {
+        PublishError::PostProcessError(format!("Could not highlight {lang}: {e}"))
+    };
+    if settings.highlight == Highlight::Inline {
+        return highlighted_html_for_string(code, syntaxes(), syntax, theme(settings)?)
+            .map_err(failed);
+    }
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes(), CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .map_err(failed)?;
+    }
+    Ok(format!(
+        "<pre class=\"hl-code\"><code class=\"language-{}\">{}</code></pre>\n",
+        html_escape(lang),
+        generator.finalize()
+    ))
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
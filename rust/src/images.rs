@@ -0,0 +1,421 @@
+//! Make the images in posts a bit friendlier.
+//!
+//! The posts point at the big originals in `imgs/`. For every `<img>` in a post that points at
+//! one of our own JPEG, PNG or WebP files, we write resized copies (one per `image_widths`,
+//! never bigger than the original) into the output directory, under the same path as the
+//! original, and point the tag at those with a `srcset`, `width`/`height` and
+//! `loading="lazy"`. Re-encoding the image leaves behind the EXIF (and any GPS) data, so
+//! the orientation gets applied first. With `webp = true` there's a WebP copy of each too.
+//!
+//! GIFs get left alone, since resizing would lose the animation.
+//!
+//! The build manifest remembers what got made from which original, so an image is only
+//! processed again if the original or the settings changed. It also remembers which originals
+//! each post points at (even ones that aren't there yet), so the post gets redone when one of
+//! them changes or turns up.
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageDecoder, ImageReader,
+};
+use regex::{Match, Regex};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{cache::BuildCache, links::internal_path, PublishError, Settings};
+
+/// The kinds of image we know how to resize.
+const FORMATS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+/// How hard to squish the JPEGs.
+const JPEG_QUALITY: u8 = 85;
+
+/// A resized copy of an image.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Variant {
+    /// Where it was written, relative to the output directory.
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What we made from an original image.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CachedImage {
+    /// Hash of the original.
+    source: String,
+    /// The settings the variants were made with.
+    options: String,
+    /// Smallest first.
+    variants: Vec<Variant>,
+    /// The WebP copies (if asked for), smallest first.
+    webp: Vec<Variant>,
+}
+
+/// Rewrite every `<img>` in `body` that points at one of our images.
+pub fn rewrite(
+    settings: &Settings,
+    cache: &mut BuildCache,
+    body: &str,
+) -> Result<String, PublishError> {
+    if settings.image_widths.is_empty() {
+        return Ok(body.to_owned());
+    }
+    let mut result = String::with_capacity(body.len());
+    let mut last = 0;
+    for (tag, attrs) in img_tags(body) {
+        result.push_str(&body[last..tag.start()]);
+        last = tag.end();
+        let source = attrs
+            .iter()
+            .find(|(name, _)| name == "src")
+            .and_then(|(_, src)| local_image(settings, src));
+        // Not ours, or someone already took care of it.
+        let Some(source) = source.filter(|_| !attrs.iter().any(|(name, _)| name == "srcset"))
+        else {
+            result.push_str(tag.as_str());
+            continue;
+        };
+        let image = match process(settings, cache, &source) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("🖼️ Could not resize {:?}: {:?}", &source, e);
+                result.push_str(tag.as_str());
+                continue;
+            }
+        };
+        result.push_str(&img_tag(settings, &image, &attrs));
+    }
+    result.push_str(&body[last..]);
+    Ok(result)
+}
+
+/// The originals that `body` points at, whether they're there or not.
+pub fn referenced(settings: &Settings, body: &str) -> Vec<String> {
+    if settings.image_widths.is_empty() {
+        return Vec::new();
+    }
+    let paths: BTreeSet<String> = img_tags(body)
+        .iter()
+        .filter_map(|(_, attrs)| attrs.iter().find(|(name, _)| name == "src"))
+        .filter_map(|(_, src)| resizable(settings, src))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    paths.into_iter().collect()
+}
+
+/// What the originals at `paths` look like right now, for a post's key in the build manifest.
+pub fn fingerprints(paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| match fs::read(path) {
+            Ok(data) => format!("{} {:x}", path, Sha256::digest(data)),
+            Err(_) => format!("{} missing", path),
+        })
+        .collect()
+}
+
+/// The `<img>` tags in `body`, with their attributes (names lower-cased, and an empty value for
+/// the ones that don't have one, which means the same thing). Any tag we can't make complete
+/// sense of gets skipped, so it's left the way it was.
+fn img_tags(body: &str) -> Vec<(Match<'_>, Vec<(String, String)>)> {
+    let img_re = Regex::new(r"(?is)<img\b([^>]*)>").expect("Image Regex altered");
+    let attr_re = Regex::new(
+        r#"(?is)([a-z][a-z0-9_:.-]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#,
+    )
+    .expect("Attribute Regex altered");
+    img_re
+        .captures_iter(body)
+        .filter_map(|tag| {
+            let inside = tag.get(1)?.as_str();
+            let mut attrs = Vec::new();
+            let mut last = 0;
+            for c in attr_re.captures_iter(inside) {
+                let whole = c.get(0)?;
+                if !is_filler(&inside[last..whole.start()]) {
+                    return None;
+                }
+                last = whole.end();
+                let value = c
+                    .get(2)
+                    .or(c.get(3))
+                    .or(c.get(4))
+                    .map(|m| m.as_str())
+                    .unwrap_or_default();
+                attrs.push((c[1].to_lowercase(), value.replace('"', "&quot;")));
+            }
+            is_filler(&inside[last..]).then(|| (tag.get(0).expect("Image Regex altered"), attrs))
+        })
+        .collect()
+}
+
+/// What's allowed between attributes (the `/` being from a `<img ... />`).
+fn is_filler(between: &str) -> bool {
+    between.chars().all(|c| c.is_whitespace() || c == '/')
+}
+
+/// The file an `<img src>` would be, if it's one of ours that we can resize.
+fn resizable(settings: &Settings, src: &str) -> Option<PathBuf> {
+    let path = internal_path(settings, Path::new(""), src)?;
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    (FORMATS.contains(&extension.as_str()) && !path.starts_with(Path::new(&settings.output)))
+        .then_some(path)
+}
+
+/// The file an `<img src>` points at, if it's one of ours that we can resize.
+fn local_image(settings: &Settings, src: &str) -> Option<PathBuf> {
+    resizable(settings, src).filter(|path| path.is_file())
+}
+
+/// Build the new tag. Everything the author put on the original is kept, except for what
+/// we're replacing. (If they set a size, that wins.)
+fn img_tag(settings: &Settings, image: &CachedImage, attrs: &[(String, String)]) -> String {
+    let largest = image.variants.last().cloned().unwrap_or_default();
+    let mut tag = format!(
+        r#"<img src="{}/{}" srcset="{}""#,
+        settings.url,
+        largest.file,
+        srcset(settings, &image.variants)
+    );
+    for (name, value) in attrs {
+        if !matches!(name.as_str(), "src" | "srcset" | "sizes" | "loading") {
+            tag.push_str(&format!(r#" {}="{}""#, name, value));
+        }
+    }
+    if !attrs
+        .iter()
+        .any(|(name, _)| name == "width" || name == "height")
+    {
+        tag.push_str(&format!(
+            r#" width="{}" height="{}""#,
+            largest.width, largest.height
+        ));
+    }
+    tag.push_str(r#" loading="lazy" />"#);
+    if image.webp.is_empty() {
+        return tag;
+    }
+    format!(
+        r#"<picture><source type="image/webp" srcset="{}" />{}</picture>"#,
+        srcset(settings, &image.webp),
+        tag
+    )
+}
+
+fn srcset(settings: &Settings, variants: &[Variant]) -> String {
+    variants
+        .iter()
+        .map(|v| format!("{}/{} {}w", settings.url, v.file, v.width))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Make (or find) the resized copies of `path`.
+fn process(
+    settings: &Settings,
+    cache: &mut BuildCache,
+    path: &Path,
+) -> Result<CachedImage, PublishError> {
+    let name = path.to_string_lossy().into_owned();
+    let output = Path::new(&settings.output);
+    let source = format!("{:x}", Sha256::digest(fs::read(path)?));
+    let options = format!("{:?} webp:{}", settings.image_widths, settings.webp);
+    if let Some(cached) = cache.images.get(&name).filter(|c| {
+        c.source == source
+            && c.options == options
+            && c.variants
+                .iter()
+                .chain(c.webp.iter())
+                .all(|v| output.join(&v.file).is_file())
+    }) {
+        trace!("🖼️ unchanged {:?}", &name);
+        return Ok(cached.clone());
+    }
+
+    info!("🖼️ Resizing {:?}", &name);
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut original = DynamicImage::from_decoder(decoder)?;
+    original.apply_orientation(orientation);
+    if original.width() == 0 || original.height() == 0 {
+        return Err(PublishError::General(format!("{:?} is empty", path)));
+    }
+    let extension = path
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let widths: BTreeSet<u32> = settings
+        .image_widths
+        .iter()
+        .map(|width| (*width).clamp(1, original.width()))
+        .collect();
+
+    let mut image = CachedImage {
+        source,
+        options,
+        ..Default::default()
+    };
+    for width in widths {
+        let height = ((original.height() as f64 * width as f64 / original.width() as f64).round()
+            as u32)
+            .max(1);
+        let resized = if width == original.width() {
+            original.clone()
+        } else {
+            original.resize_exact(width, height, FilterType::Lanczos3)
+        };
+        let variant = save(output, path, &resized, &extension)?;
+        image.variants.push(variant);
+        if settings.webp && extension != "webp" {
+            image.webp.push(save(output, path, &resized, "webp")?);
+        }
+    }
+    cache.images.insert(name, image.clone());
+    Ok(image)
+}
+
+/// Write `image` next to where the original would be in the output directory, as
+/// `name-<width>w.<extension>`.
+fn save(
+    output: &Path,
+    original: &Path,
+    image: &DynamicImage,
+    extension: &str,
+) -> Result<Variant, PublishError> {
+    let stem = original
+        .file_stem()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default();
+    let file = original.with_file_name(format!("{}-{}w.{}", stem, image.width(), extension));
+    let destination = output.join(&file);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    trace!("🖼️ Writing {:?}", &destination);
+    let writer = BufWriter::new(fs::File::create(&destination)?);
+    match extension {
+        "jpg" | "jpeg" => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(writer, JPEG_QUALITY))?,
+        "webp" if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(writer))?,
+        "webp" => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(WebPEncoder::new_lossless(writer))?,
+        _ => image.write_with_encoder(PngEncoder::new(writer))?,
+    }
+    Ok(Variant {
+        file: file.to_string_lossy().into_owned(),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(body: &str) -> Vec<Vec<(String, String)>> {
+        img_tags(body).into_iter().map(|(_, attrs)| attrs).collect()
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            attrs(
+                r#"<p><IMG SRC="/imgs/cat.png" alt='A "cat"' class=alignleft width=300 ismap /></p>"#
+            ),
+            [pairs(&[
+                ("src", "/imgs/cat.png"),
+                ("alt", "A &quot;cat&quot;"),
+                ("class", "alignleft"),
+                ("width", "300"),
+                ("ismap", ""),
+            ])]
+        );
+        assert_eq!(
+            attrs("<img src=/imgs/cat.png>"),
+            [pairs(&[("src", "/imgs/cat.png")])]
+        );
+    }
+
+    #[test]
+    fn confusing_tags_are_left_alone() {
+        assert!(img_tags(r#"<img src="/imgs/cat.png" ="oops">"#).is_empty());
+        assert!(img_tags(r#"<img src="/imgs/cat.png" alt="unclosed>"#).is_empty());
+    }
+
+    #[test]
+    fn new_tags() {
+        let settings = Settings {
+            url: "https://example.com".to_owned(),
+            ..Settings::default()
+        };
+        let variant = |width| Variant {
+            file: format!("imgs/cat-{width}.png"),
+            width,
+            height: width / 2,
+        };
+        let mut image = CachedImage {
+            variants: vec![variant(400), variant(800)],
+            ..CachedImage::default()
+        };
+        let tag = img_tag(
+            &settings,
+            &image,
+            &pairs(&[
+                ("src", "/imgs/cat.png"),
+                ("class", "alignleft"),
+                ("ismap", ""),
+            ]),
+        );
+        assert_eq!(
+            tag,
+            r#"<img src="https://example.com/imgs/cat-800.png" srcset="https://example.com/imgs/cat-400.png 400w, https://example.com/imgs/cat-800.png 800w" class="alignleft" ismap="" width="800" height="400" loading="lazy" />"#
+        );
+        // A size the author picked stays put.
+        let tag = img_tag(
+            &settings,
+            &image,
+            &pairs(&[("src", "/imgs/cat.png"), ("width", "300")]),
+        );
+        assert!(tag.contains(r#" width="300" loading"#), "{}", tag);
+        assert!(!tag.contains("height"), "{}", tag);
+
+        image.webp = vec![Variant {
+            file: "imgs/cat-400.webp".to_owned(),
+            width: 400,
+            height: 200,
+        }];
+        let tag = img_tag(&settings, &image, &pairs(&[("src", "/imgs/cat.png")]));
+        assert!(tag.starts_with(r#"<picture><source type="image/webp" srcset="https://example.com/imgs/cat-400.webp 400w" /><img "#));
+        assert!(tag.ends_with("</picture>"));
+    }
+
+    #[test]
+    fn only_our_images() {
+        let settings = Settings {
+            url: "https://example.com".to_owned(),
+            ..Settings::default()
+        };
+        let body = r#"<img src="/imgs/cat.png"> <img src=https://example.com/imgs/dog.JPG>
+<img src="https://elsewhere.com/imgs/cow.png"> <img src="/imgs/spin.gif"> <img src="/imgs/cat.png">"#;
+        assert_eq!(
+            referenced(&settings, body),
+            ["imgs/cat.png", "imgs/dog.JPG"]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Handle the `<?php include("...") ?>` bits in the templates.
+//!
+//! With `includes = "php"` (the default) they're left for PHP to deal with at request time.
+//! With `includes = "inline"` the included file gets pasted in at build time, so the output
+//! can be served by anything that can serve a file.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::{PublishError, Settings};
+
+/// How the `include()`s in the templates get resolved.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Includes {
+    /// Leave them for PHP.
+    #[default]
+    Php,
+    /// Paste the included file in when building.
+    Inline,
+}
+
+fn include_regex() -> Regex {
+    Regex::new(
+        r#"<\?(?:php)?\s*include(?:_once)?\s*\(?\s*["'](?<path>[^"']+)["']\s*\)?\s*;?\s*\?>"#,
+    )
+    .expect("Include Regex altered")
+}
+
+/// Find the file a template is trying to include. Templates are written as if they were being
+/// run from the blog's root (e.g. `archive/archive.inc`), but the generated files live in the
+/// output directory (which might not be `archive`), so look there first.
+fn resolve(settings: &Settings, include: &str) -> Option<PathBuf> {
+    let output = Path::new(&settings.output);
+    [
+        output.join(include),
+        output.join(Path::new(include).file_name()?),
+        Path::new(include).to_path_buf(),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+/// Replace every literal `include()` in `page` with the contents of the included file.
+pub fn inline(settings: &Settings, page: &str) -> Result<String, PublishError> {
+    let mut result = String::with_capacity(page.len());
+    let mut last = 0;
+    for captures in include_regex().captures_iter(page) {
+        let whole = captures.get(0).expect("Include Regex altered");
+        result.push_str(&page[last..whole.start()]);
+        match resolve(settings, &captures["path"]) {
+            Some(path) => result.push_str(&fs::read_to_string(path)?),
+            None => warn!("📎 Could not find included file {:?}", &captures["path"]),
+        }
+        last = whole.end();
+    }
+    result.push_str(&page[last..]);
+    Ok(result)
+}
+
+/// The files that `template` would pull in. (Used to tell if an inlined page is stale.)
+pub fn referenced(settings: &Settings, template: &str) -> Vec<PathBuf> {
+    include_regex()
+        .captures_iter(template)
+        .filter_map(|captures| resolve(settings, &captures["path"]))
+        .collect()
+}
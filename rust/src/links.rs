@@ -0,0 +1,256 @@
+//! `publish links`: go through the generated pages and make sure the links that point back at
+//! the blog (along with the images, stylesheets and scripts) actually go somewhere.
+//!
+//! Links are resolved the same way `publish serve` finds files: first in the output directory,
+//! then in the current directory (for `imgs/`, `style.css` and friends). `/0123` style post
+//! links go to the post's page. Anything pointing at another site is left alone.
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{check::Report, serve::percent_decode, Includes, PublishError, Settings};
+
+pub fn check_links(settings: &Settings) -> Result<(), PublishError> {
+    let output = Path::new(&settings.output);
+    if !output.is_dir() {
+        return Err(PublishError::SettingsError(format!(
+            "Nothing to check in {:?}, run publish first",
+            output
+        )));
+    }
+    let mut pages = Vec::new();
+    find_pages(settings, output, &mut pages)?;
+    pages.sort();
+    let link_re = Regex::new(r#"(?i)\b(href|src|srcset)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .expect("Link Regex altered");
+
+    let mut report = Report::default();
+    let mut links = 0;
+    for page in &pages {
+        let relative = page.strip_prefix(output).unwrap_or(page);
+        let base = relative.parent().unwrap_or(Path::new(""));
+        let label = describe(relative);
+        let content = String::from_utf8_lossy(&fs::read(page)?).into_owned();
+        // Only complain once per page.
+        let mut broken = BTreeSet::new();
+        for captures in link_re.captures_iter(&content) {
+            let value = captures
+                .get(2)
+                .or(captures.get(3))
+                .map(|m| m.as_str())
+                .unwrap_or_default();
+            // A srcset is a list of "url width" pairs.
+            let candidates: Vec<&str> = if captures[1].eq_ignore_ascii_case("srcset") {
+                value
+                    .split(',')
+                    .filter_map(|v| v.split_whitespace().next())
+                    .collect()
+            } else {
+                vec![value]
+            };
+            for link in candidates {
+                let Some(target) = internal_path(settings, base, link) else {
+                    continue;
+                };
+                links += 1;
+                if !exists(settings, &target) && broken.insert(link) {
+                    report.error(format!("{}: {:?} doesn't go anywhere", label, link));
+                }
+            }
+        }
+    }
+    report.finish(&format!("{} link(s) in {} page(s)", links, pages.len()))
+}
+
+/// Collect every generated page under `dir`. (The `.inc` files are pages too if PHP is going
+/// to include them, otherwise they're already pasted into the pages.)
+fn find_pages(
+    settings: &Settings,
+    dir: &Path,
+    pages: &mut Vec<PathBuf>,
+) -> Result<(), PublishError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // The index is just the newest post again.
+        if entry.file_type()?.is_symlink()
+            || path == Path::new(&settings.output).join(format!("index.{}", settings.extension))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            find_pages(settings, &path, pages)?;
+            continue;
+        }
+        let extension = path
+            .extension()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+        if extension == settings.extension
+            || matches!(extension, "html" | "htm")
+            || (extension == "inc" && settings.includes == Includes::Php)
+        {
+            pages.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Say where a link came from, by post number if it's a post.
+fn describe(relative: &Path) -> String {
+    let stem = relative
+        .file_stem()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default();
+    match stem.parse::<u64>() {
+        Ok(num) if relative.parent() == Some(Path::new("")) => {
+            format!("Post {:04} ({})", num, relative.display())
+        }
+        _ => relative.display().to_string(),
+    }
+}
+
+/// Turn a link into a path relative to the top of the blog, or `None` if it isn't ours.
+pub fn internal_path(settings: &Settings, base: &Path, link: &str) -> Option<PathBuf> {
+    let link = link.trim();
+    // Empty, in-page, not really a link, or still has some PHP or template in it.
+    if link.is_empty()
+        || link.starts_with('#')
+        || link.contains("<?")
+        || link.contains("{{")
+        || ["mailto:", "javascript:", "data:", "tel:"]
+            .iter()
+            .any(|scheme| link.to_lowercase().starts_with(scheme))
+    {
+        return None;
+    }
+    let link = link.split(['?', '#']).next().unwrap_or_default();
+    let ours = [Some(&settings.url), settings.short_url.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|prefix| prefix.trim_end_matches('/'))
+        .find_map(|prefix| {
+            link.strip_prefix(prefix)
+                .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+    let path = match ours {
+        Some(rest) => PathBuf::from(rest.trim_start_matches('/')),
+        None if link.contains("://") || link.starts_with("//") => return None,
+        None if link.starts_with('/') => PathBuf::from(link.trim_start_matches('/')),
+        None => base.join(link),
+    };
+    // Tidy up the `..`s (which, like a browser, stop at the top).
+    let mut clean = PathBuf::new();
+    for component in PathBuf::from(percent_decode(&path.to_string_lossy())).components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::ParentDir => {
+                clean.pop();
+            }
+            _ => {}
+        }
+    }
+    Some(clean)
+}
+
+fn exists(settings: &Settings, path: &Path) -> bool {
+    let output = Path::new(&settings.output);
+    // `/0123` is a post.
+    if let Some(num) = path.to_str().and_then(|v| v.parse::<u64>().ok()) {
+        if output
+            .join(format!("{}.{}", num, settings.extension))
+            .is_file()
+        {
+            return true;
+        }
+    }
+    [output, Path::new(".")].iter().any(|base| {
+        let candidate = base.join(path);
+        if candidate.is_dir() {
+            candidate
+                .join(format!("index.{}", settings.extension))
+                .is_file()
+        } else {
+            candidate.is_file()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(output: &Path) -> Settings {
+        Settings {
+            url: "https://blog.example.com".to_owned(),
+            short_url: Some("https://ex.am/b".to_owned()),
+            output: output.to_string_lossy().into_owned(),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn internal_paths() {
+        let settings = settings(Path::new("archive"));
+        let path = |base: &str, link: &str| {
+            internal_path(&settings, Path::new(base), link).map(|p| p.display().to_string())
+        };
+        assert_eq!(
+            path("", "https://blog.example.com/tag/rust/"),
+            Some("tag/rust".to_owned())
+        );
+        assert_eq!(path("", "https://ex.am/b/0123"), Some("0123".to_owned()));
+        assert_eq!(
+            path("tag/rust", "../../style.css?v=2"),
+            Some("style.css".to_owned())
+        );
+        assert_eq!(
+            path("tag", "/imgs/a%20cat.png"),
+            Some("imgs/a cat.png".to_owned())
+        );
+        assert_eq!(path("", "https://blog.example.com.evil/x"), None);
+        assert_eq!(path("", "https://elsewhere.com/"), None);
+        assert_eq!(path("", "//cdn.example.com/x.js"), None);
+        assert_eq!(path("", "mailto:me@example.com"), None);
+        assert_eq!(path("", "#comments"), None);
+        assert_eq!(path("", "<?php echo $x ?>"), None);
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(describe(Path::new("12.php")), "Post 0012 (12.php)");
+        assert_eq!(
+            describe(Path::new("2008/05/index.php")),
+            "2008/05/index.php"
+        );
+    }
+
+    #[test]
+    fn broken_links() {
+        let output =
+            std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(output.join("tag/rust")).unwrap();
+        let settings = settings(&output);
+        fs::write(output.join("1.php"), "Hi").unwrap();
+        fs::write(output.join("tag/rust/index.php"), "Rust").unwrap();
+        fs::write(
+            output.join("2.php"),
+            r#"<a href="/0001">One</a> <a href="https://ex.am/b/1">Also one</a>
+<a href='tag/rust/'>Rust</a> <a href="https://elsewhere.com/nope">Not ours</a>"#,
+        )
+        .unwrap();
+        assert!(check_links(&settings).is_ok());
+
+        fs::write(
+            output.join("3.php"),
+            r#"<img src="/imgs/not-there.png" srcset="/imgs/not-there-2x.png 2x">"#,
+        )
+        .unwrap();
+        assert!(check_links(&settings).is_err());
+        fs::remove_dir_all(output).unwrap();
+    }
+}
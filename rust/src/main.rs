@@ -1,23 +1,50 @@
 #![forbid(unsafe_code)]
 use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     io::{self, BufRead, Write},
-    path::Path,
+    path::{Path, PathBuf},
     process::Command,
     time::SystemTime,
 };
 
 use chrono::prelude::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
-use slog::{slog_o, Drain};
+use sha2::{Digest, Sha256};
+use slog::Drain;
 use tera::{Context, Tera};
 use thiserror::Error;
 
 #[macro_use]
 extern crate slog_scope;
 
+mod archives;
+mod cache;
+mod check;
+mod dates;
+mod front_matter;
+mod highlight;
+mod images;
+mod includes;
+mod links;
+mod markdown;
+mod related;
+mod rewrites;
+mod search;
+mod serve;
+mod shortlinks;
+mod sitemap;
+
+use cache::BuildCache;
+use dates::Zone;
+use front_matter::FrontMatter;
+use highlight::Highlight;
+use includes::Includes;
+use markdown::{MarkdownOptions, TocEntry};
+use related::Related;
+
 const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 /// The potential errors that we could encounter.
@@ -33,10 +60,29 @@ pub enum PublishError {
     TagError(#[from] serde_json::Error),
     #[error("Tera Error {0}")]
     TeraError(#[from] tera::Error),
+    #[error("Image Error {0}")]
+    ImageError(#[from] image::ImageError),
     #[error("General Error {0}")]
     General(String),
 }
 
+/// Things to do other than just publishing.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Commands {
+    /// Build into a temporary directory and serve it locally, rebuilding when things change.
+    Serve {
+        /// The port to listen on
+        #[clap(short, long, default_value_t = 8000)]
+        port: u16,
+    },
+    /// List the posts that are dated in the future, and when they'll go live.
+    Scheduled,
+    /// Look over the source files for problems, without publishing anything.
+    Check,
+    /// Look for broken links and missing images in what's already been published.
+    Links,
+}
+
 /// Command line and file arguments what set the things to do what you want.
 #[derive(Clone, Debug, Deserialize, Parser)]
 pub struct Args {
@@ -62,6 +108,57 @@ pub struct Args {
     /// Do you have a short URL? If not, reuse the `url` (--short_url)
     #[clap(long)]
     pub short_url: Option<String>,
+    /// Where the short URL pages go, in the output directory (--short-dir | "short")
+    #[clap(long)]
+    pub short_dir: Option<String>,
+    /// Write a `_redirects` file for the short URL host too (--short-redirects | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub short_redirects: Option<bool>,
+    /// File name for the Atom feed (--atom | "atom.xml")
+    #[clap(long)]
+    pub atom: Option<String>,
+    /// The rules to put in robots.txt (config file only)
+    #[clap(skip)]
+    pub robots: Option<String>,
+    /// The file extension for the generated pages (--extension | "php")
+    #[clap(long)]
+    pub extension: Option<String>,
+    /// Leave `include()`s for PHP, or inline them when building (--includes | php)
+    #[clap(long, value_enum)]
+    pub includes: Option<Includes>,
+    /// Timezone for post dates that don't have one, e.g. "America/Los_Angeles" (--timezone | local)
+    #[clap(long)]
+    pub timezone: Option<Zone>,
+    /// The widths to resize post images to (config file only | [480, 960, 1440])
+    #[clap(skip)]
+    pub image_widths: Option<Vec<u32>>,
+    /// Make WebP copies of the resized images too (--webp | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub webp: Option<bool>,
+    /// How to highlight fenced code: classes, inline or off (--highlight | classes)
+    #[clap(long, value_enum)]
+    pub highlight: Option<Highlight>,
+    /// The colours to highlight code with (config file only | "InspiredGitHub")
+    #[clap(skip)]
+    pub highlight_theme: Option<String>,
+    /// Which markdown extensions to use (the `[markdown]` section of the config file)
+    #[clap(skip)]
+    pub markdown: Option<MarkdownOptions>,
+    /// How fast people read, for the reading time (--words-per-minute | 200)
+    #[clap(long)]
+    pub words_per_minute: Option<u32>,
+    /// How many related posts to link to from each post, 0 for none (--related | 5)
+    #[clap(long)]
+    pub related: Option<u32>,
+    /// Write the redirects for the old links to `.htaccess` (config file only | True)
+    #[clap(skip)]
+    pub htaccess: Option<bool>,
+    /// Anything else to put at the top of `.htaccess` (config file only)
+    #[clap(skip)]
+    pub htaccess_extra: Option<String>,
+    /// Write the redirects for nginx too (--nginx | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub nginx: Option<bool>,
     /// Order by creation time or post number? (--by_time | False)
     #[clap(long, action=clap::ArgAction::SetTrue)]
     pub by_time: Option<bool>,
@@ -70,10 +167,22 @@ pub struct Args {
     pub by_name: Option<bool>,
     #[clap(long, action=clap::ArgAction::SetTrue)]
     pub new: Option<bool>,
+    /// Regenerate every post in the source directory, not just the recent ones (--rebuild-all | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub rebuild_all: Option<bool>,
+    /// Ignore the build cache and re-render everything (--force | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub force: Option<bool>,
+    /// Render drafts too, for previewing (--include-drafts | False)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    pub include_drafts: Option<bool>,
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Commands>,
 }
 
 /// Internal settings.
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     /// Directory containing the jinja templates (-t, --templates | "./templates")
     pub templates: String,
@@ -87,12 +196,55 @@ pub struct Settings {
     pub url: String,
     /// Do you have a short URL? If not, reuse the `url` (--short_url)
     pub short_url: Option<String>,
+    /// Where the short URL pages go, in the output directory (--short-dir | "short")
+    pub short_dir: String,
+    /// Write a `_redirects` file for the short URL host too (--short-redirects | False)
+    pub short_redirects: bool,
+    /// File name for the Atom feed (--atom | "atom.xml")
+    pub atom: String,
+    /// The rules to put in robots.txt (a `Sitemap:` line gets added for you)
+    pub robots: String,
+    /// The file extension for the generated pages (--extension | "php")
+    pub extension: String,
+    /// Leave `include()`s for PHP, or inline them when building (--includes | php)
+    pub includes: Includes,
+    /// Timezone for post dates that don't have one (--timezone | local)
+    pub timezone: Zone,
+    /// The widths to resize post images to. (Empty leaves the images alone.)
+    pub image_widths: Vec<u32>,
+    /// Make WebP copies of the resized images too (--webp | False)
+    pub webp: bool,
+    /// How to highlight fenced code: classes, inline or off (--highlight | classes)
+    pub highlight: Highlight,
+    /// The syntect theme to highlight code with.
+    pub highlight_theme: String,
+    /// Which markdown extensions to use.
+    pub markdown: MarkdownOptions,
+    /// How fast people read, for the reading time (--words-per-minute | 200)
+    pub words_per_minute: u32,
+    /// How many related posts to link to from each post, 0 for none (--related | 5)
+    pub related: u32,
+    /// Write the redirects for the old links to `.htaccess`.
+    pub htaccess: bool,
+    /// Anything else to put at the top of `.htaccess`.
+    pub htaccess_extra: String,
+    /// Write the redirects for nginx too (--nginx | False)
+    pub nginx: bool,
     /// Order by creation time or post number? (--by_time | False)
     pub by_time: bool,
     /// Order by file name (--by_name | True)
     pub by_name: bool,
     /// just create a new post
     pub new: bool,
+    /// Regenerate every post in the source directory, not just the recent ones.
+    pub rebuild_all: bool,
+    /// Ignore the build cache and re-render everything.
+    pub force: bool,
+    /// Render drafts too, for previewing.
+    pub include_drafts: bool,
+    /// What to do, if it's not just publishing.
+    #[serde(skip)]
+    pub command: Option<Commands>,
 }
 
 impl Settings {
@@ -105,9 +257,30 @@ impl Settings {
             recent: value.recent.unwrap_or(defaults.recent),
             url: value.url.unwrap_or(defaults.url),
             short_url: value.short_url.or(defaults.short_url),
+            short_dir: value.short_dir.unwrap_or(defaults.short_dir),
+            short_redirects: value.short_redirects.unwrap_or(defaults.short_redirects),
+            atom: value.atom.unwrap_or(defaults.atom),
+            robots: value.robots.unwrap_or(defaults.robots),
+            extension: value.extension.unwrap_or(defaults.extension),
+            includes: value.includes.unwrap_or(defaults.includes),
+            timezone: value.timezone.unwrap_or(defaults.timezone),
+            image_widths: value.image_widths.unwrap_or(defaults.image_widths),
+            webp: value.webp.unwrap_or(defaults.webp),
+            highlight: value.highlight.unwrap_or(defaults.highlight),
+            highlight_theme: value.highlight_theme.unwrap_or(defaults.highlight_theme),
+            markdown: value.markdown.unwrap_or(defaults.markdown),
+            words_per_minute: value.words_per_minute.unwrap_or(defaults.words_per_minute),
+            related: value.related.unwrap_or(defaults.related),
+            htaccess: value.htaccess.unwrap_or(defaults.htaccess),
+            htaccess_extra: value.htaccess_extra.unwrap_or(defaults.htaccess_extra),
+            nginx: value.nginx.unwrap_or(defaults.nginx),
             by_time: value.by_time.unwrap_or(defaults.by_time),
             by_name: value.by_name.unwrap_or(defaults.by_name),
             new: value.new.unwrap_or(defaults.new),
+            rebuild_all: value.rebuild_all.unwrap_or(defaults.rebuild_all),
+            force: value.force.unwrap_or(defaults.force),
+            include_drafts: value.include_drafts.unwrap_or(defaults.include_drafts),
+            command: value.command.or(defaults.command),
         }
     }
 }
@@ -147,6 +320,9 @@ impl Settings {
             debug!("∈ Fixing templates...");
             settings.templates = format!("{}/*", settings.templates);
         }
+        if settings.highlight != Highlight::Off {
+            highlight::theme(&settings)?;
+        }
         Ok(settings)
     }
 }
@@ -160,9 +336,30 @@ impl Default for Settings {
             recent: 10,
             url: "https://blog.unitedheroes.net".to_owned(),
             short_url: None,
+            short_dir: "short".to_owned(),
+            short_redirects: false,
+            atom: "atom.xml".to_owned(),
+            robots: "User-agent: *\nDisallow:".to_owned(),
+            extension: "php".to_owned(),
+            includes: Includes::Php,
+            timezone: Zone::Local,
+            image_widths: vec![480, 960, 1440],
+            webp: false,
+            highlight: Highlight::Classes,
+            highlight_theme: "InspiredGitHub".to_owned(),
+            markdown: MarkdownOptions::default(),
+            words_per_minute: 200,
+            related: 5,
+            htaccess: true,
+            htaccess_extra: String::new(),
+            nginx: false,
             by_time: false,
             by_name: true,
             new: false,
+            rebuild_all: false,
+            force: false,
+            include_drafts: false,
+            command: None,
         }
     }
 }
@@ -172,14 +369,28 @@ fn init_logging() -> Result<(), PublishError> {
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
     let drain = slog_envlogger::new(drain);
     let drain = slog_async::Async::new(drain).build().fuse();
-    let logger = slog::Logger::root(drain, slog_o!());
+    let logger = slog::Logger::root(drain, slog::o!());
     slog_scope::set_global_logger(logger).cancel_reset();
     slog_stdlog::init().ok();
     Ok(())
 }
 
+/// Where we are in reading the post header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Header {
+    /// Haven't seen anything yet.
+    Unknown,
+    /// The old line-by-line header, which runs until `===`.
+    Legacy,
+    /// Inside a `+++` or `---` front matter block.
+    Front(front_matter::Format),
+    /// Everything else is body.
+    Done,
+}
+
 /// Convert the markdown post into it's component elements
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 struct Post {
     /// The URL to the Post (generated from the num and name)
     pub link: String,
@@ -203,6 +414,18 @@ struct Post {
     pub timestamp: Option<SystemTime>,
     /// Number of seconds since Epoch (used by forms)
     pub date: u64,
+    /// Drafts don't get published (or numbered) unless you ask for them.
+    pub draft: bool,
+    /// The headings in the post, for a table of contents.
+    pub toc: Vec<TocEntry>,
+    /// How many words are in the post (not counting the code or HTML).
+    pub words: usize,
+    /// Roughly how many minutes it takes to read.
+    pub reading_time: u64,
+    /// The words in the post worth searching for, and how often they're used.
+    pub terms: BTreeMap<String, u32>,
+    /// Other posts that are like this one, best first.
+    pub related: Vec<Related>,
 }
 
 impl Post {
@@ -213,7 +436,7 @@ impl Post {
         let atime = handle.metadata().unwrap().created()?;
         let file: io::Lines<io::BufReader<fs::File>> = io::BufReader::new(handle).lines();
         debug!("👀 {:?}", &filepath);
-        let (num, name) = match Path::new(&filepath).file_stem() {
+        let stem = match Path::new(&filepath).file_stem() {
             Some(v) => match v.to_str() {
                 Some(v) => v,
                 None => {
@@ -227,39 +450,72 @@ impl Post {
                     "Missing stem for filename {filepath}"
                 )))
             }
+        };
+        // Anything in the drafts directory is a draft, and doesn't need a number.
+        let in_drafts = Path::new(&filepath).parent() == Some(&draft_dir(settings));
+        match stem.split_once("_") {
+            Some((num, name)) if num.parse::<u64>().is_ok() => {
+                result.num = num.parse::<u64>().unwrap_or_default();
+                result.name = name.to_owned();
+            }
+            _ if in_drafts => {
+                result.name = stem.to_owned();
+            }
+            _ => {
+                return Err(PublishError::PostProcessError(format!(
+                    "Invalid file name {filepath}"
+                )))
+            }
         }
-        .split_once("_")
-        .unwrap();
-        result.name = name.to_owned();
-        result.num = num
-            .parse::<u64>()
-            .map_err(|e| PublishError::PostProcessError(format!("Invalid file name {:?}", e)))?;
-        result.link = format!("{}/{:04}", settings.url, result.num);
-        if let Some(link) = settings.short_url.clone() {
-            result.shortlink = Some(format!("{}/{:04}", link, result.num));
-        }
-        let mut is_header = true;
+        let mut header = Header::Unknown;
+        let mut fence = 0;
+        let mut front = Vec::new();
         let mut body = Vec::new();
 
         // Read the post header and extract the interesting bits.
-        for line in file {
+        for (i, line) in file.enumerate() {
             if let Ok(line) = line {
-                if is_header {
+                if let Header::Front(format) = header {
+                    if line.trim_end() == format.fence() {
+                        FrontMatter::parse(format, &front, &filepath, fence)?
+                            .apply(&mut result, settings.timezone)?;
+                        header = Header::Done;
+                    } else {
+                        front.push(line);
+                    }
+                    continue;
+                }
+                if header != Header::Done {
                     if line.is_empty() {
                         continue;
                     }
+                    if header == Header::Unknown {
+                        if let Some(format) = front_matter::Format::from_fence(&line) {
+                            header = Header::Front(format);
+                            fence = i + 1;
+                            continue;
+                        }
+                        header = Header::Legacy;
+                    }
                     if line.starts_with("===") {
-                        is_header = false;
+                        header = Header::Done;
                     }
                     if line.starts_with("[") {
                         result.parse_tags(&line)?;
                     }
                     if line.starts_with("<!-- Date:") {
-                        result.parse_date(&line)?;
+                        result.parse_date(
+                            &line,
+                            settings.timezone,
+                            &format!("{}:{}", filepath, i + 1),
+                        )?;
                     }
                     if line.starts_with("# ") {
                         result.parse_title(&line)?;
                     }
+                    if line.to_lowercase().starts_with("draft:") {
+                        result.parse_draft(&line)?;
+                    }
                     if line.starts_with("> ") {
                         result.summary = Some(
                             format!("{} {}", result.summary.unwrap_or_default(), line.trim())
@@ -276,6 +532,23 @@ impl Post {
                 break;
             }
         }
+        if let Header::Front(format) = header {
+            return Err(PublishError::PostProcessError(format!(
+                "Front matter in {filepath} is missing its closing {:?}",
+                format.fence()
+            )));
+        }
+        result.draft |= in_drafts;
+        result.link = if result.draft || settings.includes == Includes::Inline {
+            // Static hosts can't rewrite the URL for us (and drafts don't have a number),
+            // so link straight to the file.
+            format!("{}/{}", settings.url, result.file_name(settings))
+        } else {
+            format!("{}/{:04}", settings.url, result.num)
+        };
+        if let Some(link) = settings.short_url.clone().filter(|_| !result.draft) {
+            result.shortlink = Some(format!("{}/{:04}", link.trim_end_matches('/'), result.num));
+        }
         if result.timestamp.is_none() {
             result.timestamp = Some(atime);
         }
@@ -288,8 +561,13 @@ impl Post {
             })?
             .as_secs();
         result.md_body = body.join("");
-        let parser = pulldown_cmark::Parser::new(&result.md_body);
-        pulldown_cmark::html::push_html(&mut result.body, parser);
+        (result.body, result.toc) = markdown::render(settings, &result.md_body)
+            .map_err(|e| PublishError::PostProcessError(format!("{filepath}: {e}")))?;
+        let text = markdown::plain_text(settings, &result.md_body);
+        result.words = text.split_whitespace().count();
+        result.terms = search::terms(&text);
+        result.reading_time =
+            (result.words as u64).div_ceil(settings.words_per_minute.max(1) as u64);
         Ok(result)
     }
 
@@ -309,19 +587,52 @@ impl Post {
         Ok(self)
     }
 
-    /// Extract a date from a POST header string
-    fn parse_date(&mut self, line: &str) -> Result<&mut Self, PublishError> {
+    /// Extract a date from a POST header string. (`at` is the `file:line`, for complaining.)
+    fn parse_date(&mut self, line: &str, zone: Zone, at: &str) -> Result<&mut Self, PublishError> {
         trace!("📅date: {:?}", &line);
-        let re = Regex::new(r"<!-- (Date:)? (?<ts>.*) -->").expect("Date Regex altered");
+        // Not greedy, since there may be another comment after it.
+        let re = Regex::new(r"<!-- (Date:)? (?<ts>.*?) -->").expect("Date Regex altered");
+        let date_str = match re.captures(line) {
+            Some(captures) => captures["ts"].to_owned(),
+            None => line.to_owned(),
+        };
+        self.set_timestamp(&date_str, zone, at)?;
+        Ok(self)
+    }
 
-        let date_str = re.replace(line, "$ts");
-        self.timestamp = DateTime::parse_from_rfc2822(&date_str)
-            .ok()
-            .map(DateTime::<Local>::from)
-            .map(Into::into);
+    /// Set the timestamp from a date string. See `dates::parse` for what's accepted.
+    fn set_timestamp(&mut self, date_str: &str, zone: Zone, at: &str) -> Result<(), PublishError> {
+        match dates::parse(date_str, zone) {
+            Some(timestamp) => {
+                self.timestamp = Some(timestamp);
+                Ok(())
+            }
+            None => Err(PublishError::PostProcessError(format!(
+                "{at}: Could not understand the date {:?}",
+                date_str.trim()
+            ))),
+        }
+    }
+
+    /// Is this a draft? (`draft: true` in the header.)
+    fn parse_draft(&mut self, line: &str) -> Result<&mut Self, PublishError> {
+        let value = line
+            .split_once(':')
+            .map(|(_, v)| v.trim())
+            .unwrap_or_default();
+        self.draft = matches!(value.to_lowercase().as_str(), "true" | "yes");
         Ok(self)
     }
 
+    /// The name of the file (in the output directory) for this post.
+    fn file_name(&self, settings: &Settings) -> String {
+        if self.draft {
+            format!("drafts/{}.{}", self.name, settings.extension)
+        } else {
+            format!("{}.{}", self.num, settings.extension)
+        }
+    }
+
     /// Extract the post title from the header string. (Basically just strip off the leading "# ")
     fn parse_title(&mut self, line: &str) -> Result<&mut Self, PublishError> {
         self.title = line.strip_prefix("# ").unwrap().to_owned();
@@ -349,7 +660,7 @@ impl std::fmt::Display for Post {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let tags = serde_json::json!(self.tags).to_string();
         let date = time_str(&self.timestamp.unwrap_or(<Local>::now().into()));
-        writeln!(f, "# {}\n{}\n{}", self.title, date, tags)?;
+        writeln!(f, "# {}\n<!-- Date: {} -->\n{}", self.title, date, tags)?;
         if let Some(summary) = self.summary.clone() {
             writeln!(f, "> {}", summary)?;
         }
@@ -358,6 +669,41 @@ impl std::fmt::Display for Post {
     }
 }
 
+/// The directory the templates live in (`settings.templates` is a glob).
+fn template_dir(settings: &Settings) -> PathBuf {
+    let prefix = settings.templates.split('*').next().unwrap_or_default();
+    match prefix {
+        "" => PathBuf::from("."),
+        p if p.ends_with('/') => PathBuf::from(p),
+        p => Path::new(p)
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+    }
+}
+
+/// Where the un-numbered drafts live.
+fn draft_dir(settings: &Settings) -> PathBuf {
+    Path::new(&settings.source).join("drafts")
+}
+
+/// Finish off a rendered page, inlining any `include()`s if we've been asked to.
+fn finish_page(settings: &Settings, page: String) -> Result<String, PublishError> {
+    match settings.includes {
+        Includes::Php => Ok(page),
+        Includes::Inline => includes::inline(settings, &page),
+    }
+}
+
+/// The number of seconds since the Epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 /// Return a normalized Time String for things.
 fn time_str(time: &SystemTime) -> String {
     DateTime::<Local>::from(*time)
@@ -365,11 +711,64 @@ fn time_str(time: &SystemTime) -> String {
         .to_string()
 }
 
-/// Get a list of files based on the leading number. (ideally, this should optionally sort based
-/// on the atime of the files like the python version does, but that can wait.
-fn get_latest_files(settings: &Settings) -> Result<Vec<String>, PublishError> {
+/// Turn a heading (or a tag) into something that's safe to use in a URL or `id`, and still
+/// reads like the original. Different things can come out the same (`C`, `C++` and `C#` are all
+/// `c`), so tags use `tag_slug`.
+fn slugify(tag: &str) -> String {
+    let mut slug = String::new();
+    for c in tag.trim().to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// Turn a tag into something that's safe to use in a path or URL, and that no other tag turns
+/// into. A tag that's only letters, numbers and `-`s is used as it is. Anything else (`C++`,
+/// `Café`, `old stuff`) gets as much of it as `slugify` can keep, plus a bit of a hash of the
+/// tag, so `C`, `C++` and `C#` each get their own page.
+fn tag_slug(tag: &str) -> String {
+    let tag = tag.trim();
+    if !tag.is_empty()
+        && !tag.starts_with('-')
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return tag.to_owned();
+    }
+    let hash = format!("{:x}", Sha256::digest(tag.as_bytes()));
+    let slug = slugify(tag);
+    if slug.is_empty() {
+        hash[..8].to_owned()
+    } else {
+        format!("{}-{}", slug, &hash[..8])
+    }
+}
+
+/// The path (relative to the output directory and the blog URL) for things about a tag.
+fn tag_path(tag: &str) -> String {
+    format!("tag/{}", tag_slug(tag))
+}
+
+/// Where the blog's own Atom feed lives (the name can be changed with `atom`).
+fn blog_atom_url(settings: &Settings) -> String {
+    format!("{}/{}", settings.url, settings.atom)
+}
+
+/// A tag on a post, and where its page lives (relative to the blog URL), for the templates.
+#[derive(Debug, Serialize)]
+struct TagLink<'a> {
+    name: &'a str,
+    path: String,
+}
+
+/// Get the list of all post files based on the leading number. (ideally, this should optionally
+/// sort based on the atime of the files like the python version does, but that can wait.
+fn get_all_files(settings: &Settings) -> Result<Vec<String>, PublishError> {
     let mut files: Vec<String> = Vec::new();
-    let re = Regex::new(r"^[0-9]{4}.*\.md$").expect("WTF: Regex failed to compile");
+    let re = post_name_regex();
     trace!("📁 Reading source... {}", &settings.source);
     for file in fs::read_dir(settings.source.clone()).unwrap() {
         let file = file.unwrap();
@@ -384,22 +783,155 @@ fn get_latest_files(settings: &Settings) -> Result<Vec<String>, PublishError> {
         }
     }
     files.sort();
-    let (_left, right) = files
-        .split_at_checked(files.len() - settings.recent as usize)
-        .unwrap_or((&[], &files));
-    Ok(right.to_vec())
+    Ok(files)
+}
+
+/// What a post's file has to be called to get published: `NNNN_name.md`, with at least four
+/// digits. (`publish check` complains about anything else.)
+fn post_name_regex() -> Regex {
+    Regex::new(r"^[0-9]{4,}_[^/]+\.md$").expect("WTF: Regex failed to compile")
 }
 
-/// Get a set of the latest files, read them, and return Posts for those files.
-async fn get_latest_posts(settings: &Settings) -> Result<Vec<Post>, PublishError> {
+/// Get the files in the drafts directory, sorted.
+fn get_draft_files(settings: &Settings) -> Vec<String> {
+    let mut drafts = Vec::new();
+    if let Ok(entries) = fs::read_dir(draft_dir(settings)) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "md") {
+                drafts.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    drafts.sort();
+    drafts
+}
+
+/// Get the list of files to publish, in order, along with the drafts (anything in the `drafts`
+/// directory, or with `draft: true` in the header) if we've been asked to include them. Drafts
+/// never go in the list of posts, so nothing else links to them. Posts dated in the future are
+/// held back until that time has passed.
+async fn get_publish_files(
+    settings: &Settings,
+    cache: &mut BuildCache,
+) -> Result<(Vec<String>, Vec<String>), PublishError> {
+    let mut drafts = get_draft_files(settings);
+    let all = get_all_files(settings)?;
+    cache.retain(&[all.as_slice(), drafts.as_slice()].concat());
+    let mut files = Vec::new();
+    let mut scheduled = 0;
+    let now = now_secs();
+    for file in all {
+        let post = cache.post(settings, &file).await?;
+        if post.draft {
+            drafts.push(file);
+        } else if post.date > now {
+            trace!("⏰ Holding back {:?} until {}", &file, post.date);
+            scheduled += 1;
+        } else {
+            files.push(file);
+        }
+    }
+    if scheduled > 0 {
+        info!("⏰ Holding back {} scheduled post(s)", scheduled);
+    }
+    if settings.include_drafts {
+        warn!("📝 Including {} draft(s). Don't upload this!", drafts.len());
+    } else {
+        if !drafts.is_empty() {
+            info!("📝 Holding back {} draft(s)", drafts.len());
+        }
+        drafts.clear();
+    }
+    Ok((files, drafts))
+}
+
+/// Get the (non-draft) posts that are dated in the future, soonest first.
+async fn get_scheduled_posts(
+    settings: &Settings,
+    cache: &mut BuildCache,
+) -> Result<Vec<Post>, PublishError> {
+    let now = now_secs();
+    let mut scheduled = Vec::new();
+    for file in get_all_files(settings)? {
+        let post = cache.post(settings, &file).await?;
+        if !post.draft && post.date > now {
+            scheduled.push(post);
+        }
+    }
+    scheduled.sort_by_key(|post| (post.date, post.num));
+    Ok(scheduled)
+}
+
+/// Print out what's queued up to go live.
+async fn list_scheduled(settings: &Settings) -> Result<(), PublishError> {
+    let mut cache = BuildCache::load(settings)?;
+    let scheduled = get_scheduled_posts(settings, &mut cache).await?;
+    cache.save()?;
+    if scheduled.is_empty() {
+        println!("Nothing scheduled.");
+        return Ok(());
+    }
+    let now = now_secs();
+    for post in scheduled {
+        let wait = post.date - now;
+        let until = match wait {
+            w if w >= 86_400 => format!("{} day(s)", w / 86_400),
+            w if w >= 3_600 => format!("{} hour(s)", w / 3_600),
+            w => format!("{} minute(s)", w.div_ceil(60)),
+        };
+        println!(
+            "{:04} {:?} goes live {} (in {})",
+            post.num,
+            post.title,
+            settings
+                .timezone
+                .format(post.timestamp.unwrap_or(SystemTime::now()), TIME_FORMAT),
+            until
+        );
+    }
+    Ok(())
+}
+
+/// Read the most recent of the `files` and return Posts for them.
+async fn get_latest_posts(
+    settings: &Settings,
+    files: &[String],
+) -> Result<Vec<Post>, PublishError> {
     let mut posts = Vec::new();
 
-    for filepath in get_latest_files(settings)? {
-        posts.push(Post::from_file(settings, filepath).await?)
+    for filepath in &files[files.len().saturating_sub(settings.recent as usize)..] {
+        posts.push(Post::from_file(settings, filepath.clone()).await?)
     }
     Ok(posts)
 }
 
+/// Get the header details for every Post in the source directory, sorted by date (and number).
+/// Unchanged posts come from the build cache, so none of these have a `md_body` or `body`.
+async fn get_corpus(
+    settings: &Settings,
+    files: &[String],
+    cache: &mut BuildCache,
+) -> Result<Vec<Post>, PublishError> {
+    let mut posts = Vec::new();
+    for file in files {
+        posts.push(cache.post(settings, file).await?);
+    }
+    posts.sort_by_key(|post| (post.date, post.num));
+    let mut seen = HashSet::new();
+    posts.retain(|post| {
+        let first = seen.insert(post.link.clone());
+        if !first {
+            warn!(
+                "Skipping duplicate post number {} ({})",
+                post.num, post.name
+            );
+        }
+        first
+    });
+    Ok(posts)
+}
+
 /// Write a composed Tera file for a given Post.
 async fn write_post(
     settings: &Settings,
@@ -408,7 +940,10 @@ async fn write_post(
     prev: Option<&Post>,
     next: Option<&Post>,
 ) -> Result<Option<String>, PublishError> {
-    let path = Path::new(&settings.output).join(format!("{}.php", &current.num));
+    let path = Path::new(&settings.output).join(current.file_name(settings));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
     debug!("✍️ writing post ✍️ {:?}", &path);
     let mut context = Context::new();
     context.insert("post", &current);
@@ -419,6 +954,24 @@ async fn write_post(
         context.insert("next", &next);
     }
     context.insert("url", &settings.url);
+    context.insert("php", &(settings.includes == Includes::Php));
+    context.insert("blog_atom_url", &blog_atom_url(settings));
+    let tags: Vec<TagLink> = current
+        .tags
+        .iter()
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| TagLink {
+            name: tag,
+            path: tag_path(tag),
+        })
+        .collect();
+    context.insert("tags", &tags);
+    if settings.highlight == Highlight::Classes {
+        context.insert(
+            "highlight_css",
+            &format!("{}/{}", settings.url, highlight::HIGHLIGHT_CSS),
+        );
+    }
     context.insert(
         "short_url",
         &settings.short_url.clone().unwrap_or(settings.url.clone()),
@@ -428,64 +981,192 @@ async fn write_post(
     if let Some(name) = path.to_str().map(|v| v.to_owned()) {
         let mut current_file = fs::File::create(&path)?;
         trace!("✍️ opened. {:?}", &name);
-        let _ = current_file.write_all(tera.render("index.php", &context)?.as_bytes());
+        let page = finish_page(settings, tera.render("index.php", &context)?)?;
+        let _ = current_file.write_all(page.as_bytes());
         trace!("✍ done {:?}", &name);
         return Ok(Some(name));
     }
     Ok(None)
 }
 
-/// Iterate through a set of Posts and write the files to the output directory
+/// Write the Posts that need it to the output directory, wiring up the prev/next links along the
+/// way. `files` is the full, sorted list of source files. Normally only the newest Post and its
+/// predecessor are considered (plus any Post that's never been rendered, and its neighbours),
+/// `--rebuild-all` considers every Post. So does `includes = "inline"`, since every page has
+/// its own copy of the latest posts list (which is part of the template hash, see
+/// `cache::hash_templates`). Anything whose inputs haven't
+/// changed since the last build is skipped. `related` is the related Posts for each Post (see
+/// `related::find`), by link. Returns the file name of the newest Post.
 async fn publish_posts(
     settings: &Settings,
-    posts: &[Post],
+    files: &[String],
     tera: &Tera,
+    cache: &mut BuildCache,
+    related: &HashMap<String, Vec<Related>>,
 ) -> Result<Option<String>, PublishError> {
-    let mut post_iter = posts.iter();
-    let current = post_iter.next();
-    let prev = post_iter.next();
-    let prior = post_iter.next();
-    if let Some(current) = current {
-        debug!("⦾ publishing new");
-        let index = write_post(settings, tera, current, prev, None)
-            .await
-            .map_err(|e| error!("publish_posts {:?}", e));
-        debug!("⦾ publishing prev");
-        if let Some(prev) = prev {
-            let _ = write_post(settings, tera, prev, prior, Some(current))
-                .await
-                .map_err(|e| error!("publish_posts {:?}", e));
-        }
-        debug!("⦾ done");
-        return Ok(index.unwrap());
+    let start = if settings.rebuild_all || settings.includes == Includes::Inline {
+        0
+    } else {
+        files.len().saturating_sub(2)
+    };
+    // Anything older that's never been rendered (like a scheduled post whose time has come)
+    // needs doing too, and so do its neighbours, since they should link to it now.
+    let mut todo: BTreeSet<usize> = (start..files.len()).collect();
+    for (i, file) in files[..start].iter().enumerate() {
+        if !cache.is_rendered(file) {
+            debug!("⦾ never rendered {:?}", file);
+            todo.extend(
+                [i.checked_sub(1), Some(i), Some(i + 1)]
+                    .into_iter()
+                    .flatten(),
+            );
+        }
     }
-    Ok(None)
+    // Only read the files we actually need, and only read them once.
+    let mut loaded: HashMap<usize, Post> = HashMap::new();
+    let mut skipped = Vec::new();
+    let mut index = None;
+    for i in todo {
+        let mut key = cache.key(settings, files, i)?;
+        let link = cache.post(settings, &files[i]).await?.link;
+        let related = related.get(&link).cloned().unwrap_or_default();
+        key.related = related
+            .iter()
+            .map(|r| format!("{} {}", r.link, r.title))
+            .collect();
+        if let Some(output) = cache.is_fresh(&files[i], &key) {
+            trace!("⦾ unchanged {:?}", &files[i]);
+            index = Some(output.to_owned());
+            skipped.push(files[i].clone());
+            continue;
+        }
+        let wanted = [i.checked_sub(1), Some(i), Some(i + 1)];
+        for j in wanted.into_iter().flatten().filter(|j| *j < files.len()) {
+            if let Entry::Vacant(entry) = loaded.entry(j) {
+                entry.insert(Post::from_file(settings, files[j].clone()).await?);
+            }
+        }
+        let referenced = images::referenced(settings, &loaded[&i].body);
+        key.images = images::fingerprints(&referenced);
+        let current = Post {
+            body: images::rewrite(settings, cache, &loaded[&i].body)?,
+            related,
+            ..loaded[&i].clone()
+        };
+        let prev = i.checked_sub(1).and_then(|p| loaded.get(&p));
+        let next = loaded.get(&(i + 1));
+        debug!("⦾ publishing {:?}", &files[i]);
+        match write_post(settings, tera, &current, prev, next).await {
+            Ok(Some(output)) => {
+                cache.update(&files[i], key, output.clone(), referenced);
+                index = Some(output);
+            }
+            Ok(None) => {}
+            Err(e) => error!("publish_posts {:?}", e),
+        }
+    }
+    if !skipped.is_empty() {
+        debug!("⦾ skipped {:?}", &skipped);
+        println!("Skipped {} unchanged post(s)", skipped.len());
+    }
+    Ok(index)
 }
 
-/// Append the latest Post to the end of the Category file (if it's not already in there)
-async fn update_categories(settings: &Settings, posts: &[Post]) -> Result<(), PublishError> {
-    if let Some(current) = posts.first() {
-        for tag in current.tags.clone() {
-            let cat_path = Path::new(&settings.output).join(format!("{}.inc", tag));
-            if fs::exists(&cat_path).unwrap_or(false) {
-                trace!("🗄️😺 Updating {:?}", &cat_path);
-                let content = fs::read_to_string(&cat_path)?;
-                if content.contains(&current.link) {
-                    debug!(
-                        "🗄️ Skipping adding post to {}, already included",
-                        current.title
-                    );
-                    continue;
-                }
-            }
-            // add the link
-            info!("🗄️ Generating {:?}...", &cat_path);
-            let mut file = fs::File::create(&cat_path)?;
-            writeln!(
-                &mut file,
-                "<li><a href={:?}>{}</a></li>",
-                current.link, current.title
-            )?;
+/// Group the Posts by their tags. The Posts keep the order they were given in.
+fn group_by_tag(posts: &[Post]) -> BTreeMap<&str, Vec<&Post>> {
+    let mut tags: BTreeMap<&str, Vec<&Post>> = BTreeMap::new();
+    for post in posts {
+        // A post that lists the same tag twice should still only show up once.
+        let post_tags: BTreeSet<&str> = post
+            .tags
+            .iter()
+            .map(|t| t.as_str())
+            .filter(|t| !t.is_empty())
+            .collect();
+        for tag in post_tags {
+            tags.entry(tag).or_default().push(post);
+        }
+    }
+    tags
+}
+
+/// Render the listing page (`tag/<tag>/index.<extension>`) and the `<tag>.inc` snippet for every tag,
+/// using the `category.php` and `category.inc` templates. `posts` should be every Post (see
+/// `get_corpus`) so that tags that have been removed from a post, or posts that have been
+/// deleted, drop out of the listings. Tags that no longer have any posts are cleaned up.
+async fn update_categories(
+    settings: &Settings,
+    posts: &[Post],
+    tera: &Tera,
+    cache: &mut BuildCache,
+) -> Result<(), PublishError> {
+    let tags = group_by_tag(posts);
+    for (tag, mut tag_posts) in tags.clone() {
+        // Newest first.
+        tag_posts.reverse();
+        let path = tag_path(tag);
+        let dir = Path::new(&settings.output).join(&path);
+        fs::create_dir_all(&dir)?;
+        let mut context = Context::new();
+        context.insert("tag", tag);
+        context.insert("path", &path);
+        context.insert("posts", &tag_posts);
+        context.insert("url", &settings.url);
+        context.insert("php", &(settings.includes == Includes::Php));
+        context.insert("feed_url", &format!("{}/{}/feed", settings.url, path));
+        context.insert("blog_atom_url", &blog_atom_url(settings));
+        context.insert(
+            "atom_url",
+            &format!("{}/{}/{}", settings.url, path, settings.atom),
+        );
+        debug!("🗄️ Generating {:?} ({} posts)...", &dir, tag_posts.len());
+        let page = finish_page(settings, tera.render("category.php", &context)?)?;
+        fs::write(dir.join(format!("index.{}", settings.extension)), page)?;
+        let file = fs::File::create(dir.join("list.inc"))?;
+        tera.render_to("category.inc", &context, file)?;
+    }
+    info!("🗄️ Generated {} categories", tags.len());
+    let current: BTreeSet<String> = tags.keys().map(|t| t.to_string()).collect();
+    // An old tag can end up with the same path as one we still have (an old `tag_slug`, or a
+    // file system that doesn't care about case), and that one stays.
+    let paths: HashSet<String> = tags.keys().map(|t| tag_path(t)).collect();
+    for stale in cache.tags.difference(&current) {
+        info!("🗄️ Removing empty category {:?}", stale);
+        // (That takes the `list.inc` snippet with it.)
+        let path = tag_path(stale);
+        if !paths.contains(&path) {
+            let _ = fs::remove_dir_all(Path::new(&settings.output).join(path));
+        }
+    }
+    cache.tags = current;
+    Ok(())
+}
+
+/// Render the drafts (with `--include-drafts`) into `drafts/`. They don't get a previous or next
+/// post, and nothing links to them. Without `--include-drafts`, any that were rendered before
+/// get cleaned up.
+async fn publish_drafts(
+    settings: &Settings,
+    drafts: &[String],
+    tera: &Tera,
+    cache: &mut BuildCache,
+) -> Result<(), PublishError> {
+    let dir = Path::new(&settings.output).join("drafts");
+    if !settings.include_drafts {
+        if dir.exists() {
+            info!("📝 Removing the old draft previews in {:?}", &dir);
+            fs::remove_dir_all(&dir)?;
+        }
+        return Ok(());
+    }
+    for file in drafts {
+        let post = Post::from_file(settings, file.clone()).await?;
+        let current = Post {
+            body: images::rewrite(settings, cache, &post.body)?,
+            ..post
+        };
+        if let Some(output) = write_post(settings, tera, &current, None, None).await? {
+            println!("Previewing {}", &output);
         }
     }
     Ok(())
@@ -508,12 +1189,69 @@ async fn update_archive(settings: &Settings, posts: &Vec<Post>) -> Result<(), Pu
     Ok(())
 }
 
-/// Update the RSS/CDF file based on the recent posts.
+/// Update the RSS/Atom/CDF files based on the recent posts, along with an RSS/Atom feed for every
+/// tag (under `tag/<tag>/`) with the `recent` newest posts that have it. `corpus` is every Post
+/// (see `get_corpus`), which doesn't have the bodies, so the ones that end up in a tag's feed get
+/// read again.
 async fn update_rss(
     settings: &Settings,
-    posts: &Vec<Post>,
+    posts: &[Post],
+    corpus: &[Post],
+    files: &[String],
     tera: &Tera,
+    cache: &mut BuildCache,
 ) -> Result<(), PublishError> {
+    let output = Path::new(&settings.output);
+    let all: Vec<&Post> = posts.iter().collect();
+    let context = write_feeds(settings, tera, &all, output, &settings.url, None)?;
+    info!("📰 Updating CDF");
+    let file = fs::File::create(output.join("cdf"))?;
+    tera.render_to("template.cdf", &context, file)?;
+
+    let tags: Vec<(&str, Vec<&Post>)> = group_by_tag(corpus)
+        .into_iter()
+        .map(|(tag, tag_posts)| {
+            let newest = tag_posts.len().saturating_sub(settings.recent as usize);
+            (tag, tag_posts[newest..].to_vec())
+        })
+        .collect();
+    let wanted: HashSet<&str> = tags
+        .iter()
+        .flat_map(|(_, tag_posts)| tag_posts.iter().map(|post| post.link.as_str()))
+        .collect();
+    // The recent ones are already loaded.
+    let mut full: HashMap<String, Post> = posts
+        .iter()
+        .filter(|post| wanted.contains(post.link.as_str()))
+        .map(|post| (post.link.clone(), post.clone()))
+        .collect();
+    for file in files {
+        let post = cache.post(settings, file).await?;
+        if wanted.contains(post.link.as_str()) && !full.contains_key(&post.link) {
+            full.insert(post.link, Post::from_file(settings, file.clone()).await?);
+        }
+    }
+    for (tag, tag_posts) in tags {
+        let tag_posts: Vec<&Post> = tag_posts.iter().map(|post| &full[&post.link]).collect();
+        let path = tag_path(tag);
+        let dir = output.join(&path);
+        fs::create_dir_all(&dir)?;
+        let base_url = format!("{}/{}", settings.url, path);
+        write_feeds(settings, tera, &tag_posts, &dir, &base_url, Some(tag))?;
+    }
+    Ok(())
+}
+
+/// Write the RSS and Atom feeds for `posts` into `dir` (which is served as `base_url`).
+/// Returns the context used, in case you want to render anything else with it.
+fn write_feeds(
+    settings: &Settings,
+    tera: &Tera,
+    posts: &[&Post],
+    dir: &Path,
+    base_url: &str,
+    tag: Option<&str>,
+) -> Result<Context, PublishError> {
     let mut context = Context::new();
     let mod_time = if let Some(newest_post) = posts.last() {
         newest_post.date
@@ -525,23 +1263,84 @@ async fn update_rss(
     };
     context.insert("posts", &posts);
     context.insert("mod_time", &mod_time);
-    info!("📰 Updating RSS");
-    let file = fs::File::create(Path::new(&settings.output).join("feed"))?;
+    context.insert("url", &settings.url);
+    context.insert("host", host(&settings.url));
+    context.insert("tag", &tag);
+    info!("📰 Updating RSS {:?}", dir.join("feed"));
+    context.insert("feed_url", &format!("{}/feed", base_url));
+    let file = fs::File::create(dir.join("feed"))?;
     tera.render_to("template.rss", &context, file)?;
-    info!("📰 Updating CDF");
-    let file = fs::File::create(Path::new(&settings.output).join("cdf"))?;
-    tera.render_to("template.cdf", &context, file)?;
-    Ok(())
+    info!("📰 Updating Atom {:?}", dir.join(&settings.atom));
+    context.insert("feed_url", &format!("{}/{}", base_url, settings.atom));
+    let file = fs::File::create(dir.join(&settings.atom))?;
+    tera.render_to("template.atom", &context, file)?;
+    Ok(context)
+}
+
+/// The host name in a URL, for the Atom entry `<id>`s (`tag:<host>,<date>:<num>`, which don't
+/// change when the post's link does).
+fn host(url: &str) -> &str {
+    let after_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let host = after_scheme.split('/').next().unwrap_or_default();
+    // No user or port.
+    let host = host.rsplit('@').next().unwrap_or_default();
+    host.split(':').next().unwrap_or_default()
 }
 
-/// Set the index to point to the most recent file.
+/// Set the index to point to the most recent file. (Static hosts don't always follow symlinks,
+/// so when we're inlining includes, the index is a copy.)
 async fn set_index(settings: &Settings, latest: &str) -> Result<(), PublishError> {
     info!("📁 Setting index");
-    let index = Path::new(&settings.output).join("index.php");
+    let index = Path::new(&settings.output).join(format!("index.{}", settings.extension));
     let _ = fs::remove_file(&index).map_err(|e| {
         warn!("Could not delete old index file {:?}", e);
     });
-    std::os::unix::fs::symlink(latest, index)?;
+    match settings.includes {
+        Includes::Php => {
+            // The link lives in the output directory, so point at the file relative to that.
+            let target = Path::new(latest)
+                .strip_prefix(&settings.output)
+                .unwrap_or(Path::new(latest));
+            std::os::unix::fs::symlink(target, index)?;
+        }
+        Includes::Inline => {
+            fs::copy(latest, index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Build everything: the posts that need it, the index, the categories, the sitemap and the feeds.
+async fn publish(settings: &Settings, tera: &Tera) -> Result<(), PublishError> {
+    let mut cache = BuildCache::load(settings)?;
+    let (files, drafts) = get_publish_files(settings, &mut cache).await?;
+    let posts = get_latest_posts(settings, &files).await?;
+    // The archive comes first, since it may get inlined into the posts.
+    debug!("🏤 updating archive");
+    update_archive(settings, &posts).await?;
+    highlight::write_css(settings)?;
+    let corpus = get_corpus(settings, &files, &mut cache).await?;
+    let related = related::find(settings, &corpus);
+    let index = publish_posts(settings, &files, tera, &mut cache, &related).await?;
+    if let Some(index) = index {
+        set_index(settings, &index).await?;
+        println!("Published {}", &index);
+    }
+    publish_drafts(settings, &drafts, tera, &mut cache).await?;
+    debug!("🗄️ updating categories");
+    update_categories(settings, &corpus, tera, &mut cache).await?;
+    debug!("📅 updating archives");
+    archives::update_archives(settings, &corpus, tera, &mut cache)?;
+    debug!("🔎 updating search");
+    search::update_search(settings, &corpus, tera)?;
+    debug!("🗺️ updating sitemap");
+    sitemap::update_sitemap(settings, &corpus)?;
+    sitemap::update_robots(settings)?;
+    rewrites::update_rewrites(settings, &corpus)?;
+    shortlinks::update_short_links(settings, &corpus)?;
+    debug!("📰 updating syndication");
+    update_rss(settings, &posts, &corpus, &files, tera, &mut cache).await?;
+    cache.save()?;
     Ok(())
 }
 
@@ -550,19 +1349,27 @@ async fn main() -> Result<(), PublishError> {
     init_logging()?;
     let settings = Settings::new()?;
 
-    let tera = Tera::new(&settings.templates)?;
-    let mut posts = get_latest_posts(&settings).await?;
-    posts.reverse();
-    let posts = get_latest_posts(&settings).await?;
     if settings.new {
+        let mut cache = BuildCache::load(&settings)?;
+        let (files, _) = get_publish_files(&settings, &mut cache).await?;
+        let posts = get_latest_posts(&settings, &files).await?;
         if let Some(latest) = posts.last() {
-            info!("Latest num: {}", latest.num);
+            // Scheduled posts aren't published yet, but they still have their numbers.
+            let latest_num = get_scheduled_posts(&settings, &mut cache)
+                .await?
+                .iter()
+                .map(|post| post.num)
+                .fold(latest.num, u64::max);
+            info!("Latest num: {}", latest_num);
             let new_post = Post {
-                num: latest.num + 1,
+                num: latest_num + 1,
                 tags: ["crap".to_owned()].to_vec(),
                 title: "To Be Determined".to_owned(),
                 name: "tbd".to_owned(),
-                summary: Some("Remember to change the name of this file to match the short summary!".to_owned()),
+                summary: Some(
+                    "Remember to change the name of this file to match the short summary!"
+                        .to_owned(),
+                ),
                 date: SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .unwrap()
@@ -580,16 +1387,72 @@ async fn main() -> Result<(), PublishError> {
             return Ok(());
         }
     }
-    let index = publish_posts(&settings, &posts, &tera).await?;
-    if let Some(index) = index {
-        set_index(&settings, &index).await?;
-        println!("Published {}", &index);
+    match settings.command {
+        Some(Commands::Serve { port }) => serve::serve(&settings, port).await,
+        Some(Commands::Scheduled) => list_scheduled(&settings).await,
+        Some(Commands::Check) => check::check(&settings).await,
+        Some(Commands::Links) => links::check_links(&settings),
+        None => {
+            let tera = Tera::new(&settings.templates)?;
+            publish(&settings, &tera).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_tags_keep_their_name() {
+        assert_eq!(tag_slug("rust"), "rust");
+        assert_eq!(tag_slug("Rust"), "Rust");
+        assert_eq!(tag_slug("web-dev"), "web-dev");
+        assert_eq!(tag_slug(" rust "), "rust");
+        assert_eq!(tag_path("rust"), "tag/rust");
+    }
+
+    #[test]
+    fn tags_that_look_alike_get_their_own_slug() {
+        let tags = [
+            "C", "c", "C++", "C#", "c++", "web dev", "web-dev", "Web Dev", "-c", "Café", "cafe",
+        ];
+        let slugs: BTreeSet<String> = tags.iter().map(|tag| tag_slug(tag)).collect();
+        assert_eq!(slugs.len(), tags.len(), "{:?}", slugs);
+        // Still readable, though.
+        assert!(tag_slug("C++").starts_with("c-"));
+        assert!(tag_slug("Café").starts_with("caf-"));
+        // And the same every time.
+        assert_eq!(tag_slug("C++"), tag_slug("C++"));
+    }
+
+    #[test]
+    fn every_tag_gets_a_slug() {
+        for tag in ["", "   ", "日本", "!!!", "-"] {
+            let slug = tag_slug(tag);
+            assert!(!slug.is_empty(), "{:?}", tag);
+            assert!(
+                slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                "{:?} -> {:?}",
+                tag,
+                slug
+            );
+        }
+        assert_ne!(tag_slug("日本"), tag_slug("!!!"));
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  C++ & You "), "c-you");
+        assert_eq!(slugify("日本"), "");
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(host("https://blog.example.com"), "blog.example.com");
+        assert_eq!(host("https://blog.example.com:8080/b/"), "blog.example.com");
+        assert_eq!(host("http://me@example.com/"), "example.com");
+        assert_eq!(host("example.com/blog"), "example.com");
     }
-    debug!("🗄️ updating categories");
-    update_categories(&settings, &posts).await?;
-    debug!("📰 updating syndication");
-    update_rss(&settings, &posts, &tera).await?;
-    debug!("🏤 updating archive");
-    update_archive(&settings, &posts).await?;
-    Ok(())
 }
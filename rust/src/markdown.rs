@@ -0,0 +1,419 @@
+//! Turning the markdown into HTML.
+//!
+//! pulldown-cmark only does plain CommonMark unless you ask for more, so the extensions can
+//! be turned on (or off) in the `[markdown]` section of the config file. The defaults match
+//! what the old Python version did (it had `tables` and `sane_lists`; CommonMark lists are
+//! already sane), so the converted posts come out the way they used to.
+//!
+//! Every heading gets an `id` (made from its text, unless it already has one) so it can be
+//! linked to, and the headings are collected into a table of contents. A paragraph that's
+//! just `[TOC]` gets replaced by that table.
+use std::collections::HashSet;
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    highlight::{self, html_escape},
+    slugify, PublishError, Settings,
+};
+
+/// Where to put the table of contents in a post.
+const TOC_MARKER: &str = "[TOC]";
+
+/// Which markdown extensions to use.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MarkdownOptions {
+    /// `| pipe | tables |`
+    pub tables: bool,
+    /// `[^1]` references, with the notes collected at the bottom.
+    pub footnotes: bool,
+    /// `~~struck~~`
+    pub strikethrough: bool,
+    /// `- [x] done`
+    pub tasklists: bool,
+    /// Curly quotes, en and em dashes, and ellipses.
+    pub smart_punctuation: bool,
+    /// `# Heading {#id .class}`
+    pub heading_attributes: bool,
+    /// `$inline$` and `$$display$$` math (as `<span class="math ...">` for something like
+    /// KaTeX to pick up).
+    pub math: bool,
+    /// GitHub's `> [!NOTE]` style blockquotes.
+    pub gfm: bool,
+    /// `term` followed by `: definition`
+    pub definition_list: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            tables: true,
+            footnotes: false,
+            strikethrough: false,
+            tasklists: false,
+            smart_punctuation: false,
+            heading_attributes: false,
+            math: false,
+            gfm: false,
+            definition_list: false,
+        }
+    }
+}
+
+impl MarkdownOptions {
+    fn options(&self) -> Options {
+        let mut options = Options::empty();
+        for (on, option) in [
+            (self.tables, Options::ENABLE_TABLES),
+            (self.footnotes, Options::ENABLE_FOOTNOTES),
+            (self.strikethrough, Options::ENABLE_STRIKETHROUGH),
+            (self.tasklists, Options::ENABLE_TASKLISTS),
+            (self.smart_punctuation, Options::ENABLE_SMART_PUNCTUATION),
+            (self.heading_attributes, Options::ENABLE_HEADING_ATTRIBUTES),
+            (self.math, Options::ENABLE_MATH),
+            (self.gfm, Options::ENABLE_GFM),
+            (self.definition_list, Options::ENABLE_DEFINITION_LIST),
+        ] {
+            options.set(option, on);
+        }
+        options
+    }
+}
+
+/// A heading in the table of contents, along with the (lower level) headings under it.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TocEntry {
+    /// 1 for `<h1>`, 2 for `<h2>`...
+    pub level: u8,
+    /// The heading's `id`, so `#id` links to it.
+    pub id: String,
+    /// The text of the heading.
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Render a post's markdown, returning the HTML and the table of contents.
+pub fn render(
+    settings: &Settings,
+    markdown: &str,
+) -> Result<(String, Vec<TocEntry>), PublishError> {
+    let parser = Parser::new_ext(markdown, settings.markdown.options());
+    let events = highlight::highlight(settings, parser)?;
+    let (mut events, toc) = anchor_headings(events);
+    let marker = toc_html(&toc);
+    replace_marker(&mut events, &marker);
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+    Ok((body, toc))
+}
+
+/// The words someone would actually read, so not the code blocks or the HTML tags (but what's
+/// between the tags counts, since the posts from WordPress are nothing but HTML).
+pub fn plain_text(settings: &Settings, markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_code = false;
+    let mut in_tag = false;
+    for event in Parser::new_ext(markdown, settings.markdown.options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            Event::Text(words) | Event::Code(words) if !in_code => text.push_str(&words),
+            Event::Html(html) => push_html_text(&html, true, &mut in_tag, &mut text),
+            Event::InlineHtml(html) => {
+                push_html_text(&html, !is_formatting(&html), &mut in_tag, &mut text)
+            }
+            // `*a*b` is one word, but the end of a paragraph isn't.
+            Event::End(
+                TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough
+                | TagEnd::Link
+                | TagEnd::Image,
+            ) => {}
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Is `tag` one that just dresses up the words in it (`<em>`, `<a>`), rather than one that
+/// comes between them (`<br>`)?
+fn is_formatting(tag: &str) -> bool {
+    let name: String = tag
+        .trim_start_matches(['<', '/'])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    matches!(
+        name.to_lowercase().as_str(),
+        "a" | "abbr"
+            | "b"
+            | "cite"
+            | "code"
+            | "del"
+            | "em"
+            | "i"
+            | "ins"
+            | "kbd"
+            | "mark"
+            | "q"
+            | "s"
+            | "small"
+            | "span"
+            | "strong"
+            | "sub"
+            | "sup"
+            | "u"
+    )
+}
+
+/// Add the text in a bit of HTML to `text`, without the tags. A tag can be split over a few
+/// lines of an HTML block, so `in_tag` carries over from one bit to the next. Tags `separate`
+/// words (`<p>one</p><p>two</p>` is two), except for the ones that dress up a bit of a
+/// paragraph (`<em>a</em>b` is one word).
+fn push_html_text(html: &str, separate: bool, in_tag: &mut bool, text: &mut String) {
+    let mut chars = html.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' if !*in_tag => *in_tag = true,
+            '>' if *in_tag => {
+                *in_tag = false;
+                if separate {
+                    text.push(' ');
+                }
+            }
+            _ if *in_tag => {}
+            '&' => {
+                // Just enough of the entities that `&rsquo;` doesn't become a word of its own.
+                let rest = chars.as_str();
+                match rest.find(';').filter(|end| *end <= 8) {
+                    Some(end) => {
+                        text.push(match &rest[..end] {
+                            "amp" => '&',
+                            "rsquo" | "lsquo" | "apos" | "#39" | "#8217" => '\'',
+                            _ => ' ',
+                        });
+                        chars = rest[end + 1..].chars();
+                    }
+                    None => text.push(c),
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+}
+
+/// Give every heading an `id`, and collect them.
+fn anchor_headings(events: Vec<Event>) -> (Vec<Event>, Vec<TocEntry>) {
+    let mut result = Vec::with_capacity(events.len());
+    let mut toc = Vec::new();
+    // The ids picked by hand (`# Heading {#id}`) are spoken for, even the ones further down.
+    let mut used: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+    // The heading we're in the middle of, and what's in it so far.
+    let mut heading: Option<(Tag, Vec<Event>)> = None;
+    for event in events {
+        match (event, &mut heading) {
+            (Event::Start(tag @ Tag::Heading { .. }), None) => heading = Some((tag, Vec::new())),
+            (Event::End(TagEnd::Heading(level)), Some(_)) => {
+                let Some((
+                    Tag::Heading {
+                        id, classes, attrs, ..
+                    },
+                    inner,
+                )) = heading.take()
+                else {
+                    continue;
+                };
+                let title: String = inner
+                    .iter()
+                    .filter_map(|e| match e {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let id = match id {
+                    Some(id) => id.to_string(),
+                    None => unique(&mut used, &slugify(&title)),
+                };
+                used.insert(id.clone());
+                insert(
+                    &mut toc,
+                    TocEntry {
+                        level: level as u8,
+                        id: id.clone(),
+                        title: title.trim().to_owned(),
+                        children: Vec::new(),
+                    },
+                );
+                result.push(Event::Start(Tag::Heading {
+                    level,
+                    id: Some(CowStr::from(id)),
+                    classes,
+                    attrs,
+                }));
+                result.extend(inner);
+                result.push(Event::End(TagEnd::Heading(level)));
+            }
+            (event, Some((_, inner))) => inner.push(event),
+            (event, None) => result.push(event),
+        }
+    }
+    (result, toc)
+}
+
+/// Make `slug` unique by sticking a number on the end if we need to.
+fn unique(used: &mut HashSet<String>, slug: &str) -> String {
+    let slug = if slug.is_empty() { "section" } else { slug };
+    let mut candidate = slug.to_owned();
+    let mut count = 1;
+    while used.contains(&candidate) {
+        candidate = format!("{}-{}", slug, count);
+        count += 1;
+    }
+    candidate
+}
+
+/// Put `entry` under the last heading that's a higher level than it, if there is one.
+fn insert(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+    match entries.last_mut() {
+        Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+        _ => entries.push(entry),
+    }
+}
+
+fn toc_html(toc: &[TocEntry]) -> String {
+    fn list(entries: &[TocEntry], html: &mut String) {
+        html.push_str("<ul>");
+        for entry in entries {
+            html.push_str(&format!(
+                "<li><a href=\"#{}\">{}</a>",
+                html_escape(&entry.id),
+                html_escape(&entry.title)
+            ));
+            if !entry.children.is_empty() {
+                list(&entry.children, html);
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ul>");
+    }
+    let mut html = String::from("<nav class=\"toc\">");
+    list(toc, &mut html);
+    html.push_str("</nav>\n");
+    html
+}
+
+/// Swap any paragraph that's just `[TOC]` for the table of contents.
+fn replace_marker(events: &mut Vec<Event>, toc: &str) {
+    let mut i = 0;
+    while i < events.len() {
+        if events[i] != Event::Start(Tag::Paragraph) {
+            i += 1;
+            continue;
+        }
+        // The marker may come through as a few bits of text ("[", "TOC", "]").
+        let Some(end) = events[i..]
+            .iter()
+            .position(|e| *e == Event::End(TagEnd::Paragraph))
+            .map(|end| i + end)
+        else {
+            break;
+        };
+        let mut text = String::new();
+        let only_text = events[i + 1..end].iter().all(|e| match e {
+            Event::Text(t) => {
+                text.push_str(t);
+                true
+            }
+            _ => false,
+        });
+        if only_text && text.trim() == TOC_MARKER {
+            events.splice(i..=end, [Event::Html(CowStr::from(toc.to_owned()))]);
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(settings: &Settings, markdown: &str) -> Vec<String> {
+        let (_, toc) = render(settings, markdown).unwrap();
+        let mut ids = Vec::new();
+        let mut todo: Vec<&TocEntry> = toc.iter().rev().collect();
+        while let Some(entry) = todo.pop() {
+            ids.push(entry.id.clone());
+            todo.extend(entry.children.iter().rev());
+        }
+        ids
+    }
+
+    #[test]
+    fn heading_ids() {
+        let settings = Settings::default();
+        assert_eq!(
+            ids(
+                &settings,
+                "# Intro\n\n## Intro\n\n# Intro\n\n# ???\n\n# ¿¿¿\n\n# Intro 1"
+            ),
+            [
+                "intro",
+                "intro-1",
+                "intro-2",
+                "section",
+                "section-1",
+                "intro-1-1"
+            ]
+        );
+    }
+
+    #[test]
+    fn picked_heading_ids() {
+        let mut settings = Settings::default();
+        settings.markdown.heading_attributes = true;
+        // The one picked by hand wins, even if it comes later.
+        assert_eq!(
+            ids(&settings, "# Intro\n\n# Other {#intro}\n\n# Intro"),
+            ["intro-1", "intro", "intro-2"]
+        );
+        let (body, _) = render(&settings, "# Intro\n\n# Other {#intro}").unwrap();
+        assert!(body.contains(r#"<h1 id="intro-1">Intro</h1>"#), "{}", body);
+        assert!(body.contains(r#"<h1 id="intro">Other</h1>"#), "{}", body);
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let (_, toc) = render(
+            &Settings::default(),
+            "# One\n\n## Two\n\n### Three\n\n# Four",
+        )
+        .unwrap();
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].children[0].children[0].title, "Three");
+        assert_eq!(toc[1].title, "Four");
+    }
+
+    #[test]
+    fn plain_text_skips_code_and_tags() {
+        let settings = Settings::default();
+        let text = plain_text(
+            &settings,
+            "Some *words*.\n\n```\nnot_words();\n```\n\n<p>I didn&rsquo;t <a\nhref=\"x\">type</a> it.</p>",
+        );
+        let words: Vec<&str> = text.split_whitespace().collect();
+        assert_eq!(words, ["Some", "words.", "I", "didn't", "type", "it."]);
+        let text = plain_text(&settings, "One<br>two <em>thr</em>ee [four](x).");
+        let words: Vec<&str> = text.split_whitespace().collect();
+        assert_eq!(words, ["One", "two", "three", "four."]);
+    }
+}
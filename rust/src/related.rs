@@ -0,0 +1,208 @@
+//! "You might also like" links for the bottom of each post.
+//!
+//! Every post gets scored against every other post on two things:
+//!
+//! * The tags they share. A rare tag says a lot more than one that's on half the posts, so each
+//!   shared tag is worth `ln(posts / posts with the tag)`. (A tag on every post is worth nothing.)
+//! * How alike the words are. A post's most common words (`MAX_TERMS` of its `terms`) are
+//!   weighted the same way by how rare they are, and the cosine similarity of those is scaled by
+//!   `TERM_WEIGHT`.
+//!
+//! The best `related` of them (if they scored anything at all) end up in `post.related`.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{Post, Settings};
+
+/// How much two posts with exactly the same words count for, compared to sharing a tag.
+const TERM_WEIGHT: f64 = 3.0;
+/// How many of a post's words to compare.
+const MAX_TERMS: usize = 50;
+/// Just enough of a related post to link to it.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Related {
+    pub link: String,
+    pub title: String,
+    pub num: u64,
+    pub date: u64,
+}
+
+/// Find the related posts for every post in `posts`, keyed by the post's link.
+pub fn find(settings: &Settings, posts: &[Post]) -> HashMap<String, Vec<Related>> {
+    let mut result = HashMap::new();
+    if settings.related == 0 || posts.len() < 2 {
+        return result;
+    }
+    let total = posts.len() as f64;
+    let rarity = |count: usize| (total / count as f64).ln();
+
+    // Which posts have each tag and each word.
+    let tags: Vec<BTreeSet<&str>> = posts
+        .iter()
+        .map(|post| {
+            post.tags
+                .iter()
+                .map(|t| t.as_str())
+                .filter(|t| !t.is_empty())
+                .collect()
+        })
+        .collect();
+    let mut tagged: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut used: HashMap<&str, Vec<usize>> = HashMap::new();
+    let terms: Vec<Vec<(&str, u32)>> = posts.iter().map(top_terms).collect();
+    for i in 0..posts.len() {
+        for tag in &tags[i] {
+            tagged.entry(tag).or_default().push(i);
+        }
+        for (term, _) in &terms[i] {
+            used.entry(term).or_default().push(i);
+        }
+    }
+    let weights: Vec<BTreeMap<&str, f64>> = terms
+        .iter()
+        .map(|terms| {
+            terms
+                .iter()
+                .map(|(term, count)| (*term, *count as f64 * rarity(used[term].len())))
+                .collect()
+        })
+        .collect();
+    let lengths: Vec<f64> = weights
+        .iter()
+        .map(|w| w.values().map(|v| v * v).sum::<f64>().sqrt())
+        .collect();
+
+    for (i, post) in posts.iter().enumerate() {
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for tag in &tags[i] {
+            let others = &tagged[tag];
+            for j in others {
+                *scores.entry(*j).or_default() += rarity(others.len());
+            }
+        }
+        if lengths[i] > 0.0 {
+            for (term, weight) in &weights[i] {
+                for j in &used[term] {
+                    if lengths[*j] > 0.0 {
+                        *scores.entry(*j).or_default() +=
+                            TERM_WEIGHT * weight * weights[*j][term] / (lengths[i] * lengths[*j]);
+                    }
+                }
+            }
+        }
+        scores.remove(&i);
+        let mut ranked: Vec<(usize, f64)> = scores
+            .into_iter()
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        // Best first, and the newer post wins a tie.
+        ranked.sort_by(|a, b| {
+            b.1.total_cmp(&a.1).then_with(|| {
+                (posts[b.0].date, posts[b.0].num).cmp(&(posts[a.0].date, posts[a.0].num))
+            })
+        });
+        ranked.truncate(settings.related as usize);
+        trace!("🔗 {:?} is like {:?}", &post.link, &ranked);
+        result.insert(
+            post.link.clone(),
+            ranked
+                .into_iter()
+                .map(|(j, _)| Related {
+                    link: posts[j].link.clone(),
+                    title: posts[j].title.clone(),
+                    num: posts[j].num,
+                    date: posts[j].date,
+                })
+                .collect(),
+        );
+    }
+    result
+}
+
+/// The words a post uses most. (Ties go alphabetically, so it's the same every time.)
+fn top_terms(post: &Post) -> Vec<(&str, u32)> {
+    let mut terms: Vec<(&str, u32)> = post
+        .terms
+        .iter()
+        .map(|(term, count)| (term.as_str(), *count))
+        .collect();
+    terms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    terms.truncate(MAX_TERMS);
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(num: u64, tags: &[&str], words: &[&str]) -> Post {
+        let mut terms = BTreeMap::new();
+        for word in words {
+            *terms.entry(word.to_string()).or_default() += 1;
+        }
+        Post {
+            num,
+            link: format!("/{num}"),
+            title: format!("Post {num}"),
+            date: 1_729_522_800 + num,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            terms,
+            ..Post::default()
+        }
+    }
+
+    fn links(found: &HashMap<String, Vec<Related>>, num: u64) -> Vec<&str> {
+        found[&format!("/{num}")]
+            .iter()
+            .map(|r| r.link.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn rare_tags_count_more() {
+        let posts = [
+            post(1, &["blog", "rust", "emacs"], &[]),
+            post(2, &["blog", "rust"], &[]),
+            post(3, &["blog", "rust"], &[]),
+            post(4, &["blog", "emacs"], &[]),
+            post(5, &["blog"], &[]),
+        ];
+        let settings = Settings {
+            related: 5,
+            ..Settings::default()
+        };
+        let found = find(&settings, &posts);
+        // `emacs` is rarer than `rust`, and the newer post wins the tie.
+        assert_eq!(links(&found, 1), ["/4", "/3", "/2"]);
+        // Everything's a `blog` post, so that doesn't count.
+        assert!(links(&found, 5).is_empty());
+    }
+
+    #[test]
+    fn alike_words_count_too() {
+        let posts = [
+            post(1, &[], &["borrow", "checker", "lifetime", "borrow"]),
+            post(2, &[], &["borrow", "lifetime", "checker"]),
+            post(3, &[], &["sourdough", "starter", "borrow"]),
+            post(4, &[], &["sourdough", "flour"]),
+        ];
+        let settings = Settings {
+            related: 1,
+            ..Settings::default()
+        };
+        let found = find(&settings, &posts);
+        assert_eq!(links(&found, 1), ["/2"]);
+        assert_eq!(links(&found, 4), ["/3"]);
+    }
+
+    #[test]
+    fn turned_off() {
+        let posts = [post(1, &["rust"], &[]), post(2, &["rust"], &[])];
+        let settings = Settings {
+            related: 0,
+            ..Settings::default()
+        };
+        assert!(find(&settings, &posts).is_empty());
+    }
+}
@@ -0,0 +1,380 @@
+//! Rewrite rules for the web server, made from the posts we actually have.
+//!
+//! This replaces the hand-kept `htaccess`, which had to pad the post numbers out with a rule for
+//! every number of digits. The old links get a permanent redirect to where the thing lives now:
+//!
+//! * WordPress's `archives/p/N/...` and `?p=N`
+//! * the old file name style links (`0123_my-post`)
+//! * the post's name, by itself or after a date (`my-post/`, `2008/05/my-post/`)
+//! * WordPress's category pages (`category/tech/`, `archives/p/category/tech/`, and their feeds)
+//!
+//! The ones with the post number in them only need a rule each, since the number goes straight
+//! through (`/123` is the same post as `/0123`). The names and tags need one per post or tag,
+//! which is why they're made from the posts we actually have. With `includes = "php"` the
+//! `/0123` post links get served from the post's file too.
+//!
+//! `.htaccess` (for Apache) goes in the output directory, unless `htaccess = false`. With
+//! `--nginx` there's an `nginx-rewrites.conf` to `include` in the `server` block as well.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    highlight::HIGHLIGHT_CSS, search::SEARCH_INDEX, slugify, tag_path, Includes, Post,
+    PublishError, Settings,
+};
+
+pub const HTACCESS: &str = ".htaccess";
+pub const NGINX: &str = "nginx-rewrites.conf";
+
+/// What else lives at the top of the blog, that a post's name can't take over. (Along with the
+/// `short_dir`, the Atom feed and the pages, see `usable_name`.)
+const RESERVED: [&str; 11] = [
+    "tag",
+    "archives",
+    "drafts",
+    "imgs",
+    "category",
+    "feed",
+    "cdf",
+    "robots.txt",
+    "sitemap.xml",
+    SEARCH_INDEX,
+    HIGHLIGHT_CSS,
+];
+
+/// A permanent redirect for an old link.
+#[derive(Debug)]
+struct Redirect {
+    /// Regex for the path, relative to the top of the blog (no leading `/`).
+    path: String,
+    /// Where it goes now. (Can use the groups from `path`.)
+    to: String,
+}
+
+pub fn update_rewrites(settings: &Settings, posts: &[Post]) -> Result<(), PublishError> {
+    if !settings.htaccess && !settings.nginx {
+        return Ok(());
+    }
+    let redirects = redirects(settings, posts);
+    let output = Path::new(&settings.output);
+    if settings.htaccess {
+        let path = output.join(HTACCESS);
+        info!("🔀 Writing {:?} ({} redirects)", &path, redirects.len());
+        write_htaccess(settings, &redirects, fs::File::create(path)?)?;
+    }
+    if settings.nginx {
+        let path = output.join(NGINX);
+        info!("🔀 Writing {:?} ({} redirects)", &path, redirects.len());
+        write_nginx(settings, &redirects, fs::File::create(path)?)?;
+    }
+    Ok(())
+}
+
+/// Where the post whose number is in `num` (a group from the rule, like `$1`) lives now.
+fn post_url(settings: &Settings, num: &str) -> String {
+    match settings.includes {
+        // The "Posts, by number" rule takes care of the rest.
+        Includes::Php => format!("{}/{}", settings.url, num),
+        Includes::Inline => format!("{}/{}.{}", settings.url, num, settings.extension),
+    }
+}
+
+fn redirects(settings: &Settings, posts: &[Post]) -> Vec<Redirect> {
+    let mut redirects = vec![
+        Redirect {
+            path: "^archives/p/0*([0-9]+)(/.*)?$".to_owned(),
+            to: post_url(settings, "$1"),
+        },
+        Redirect {
+            path: "^0*([0-9]+)_[^/]*$".to_owned(),
+            to: post_url(settings, "$1"),
+        },
+    ];
+    // A name can only go to one post (the first one to use it).
+    let mut names = BTreeSet::new();
+    for post in posts.iter().filter(|post| !post.draft) {
+        if usable_name(settings, &post.name) && names.insert(post.name.clone()) {
+            redirects.push(Redirect {
+                path: format!(
+                    "^([0-9]{{4}}/[0-9]{{2}}/([0-9]{{2}}/)?)?{}/?$",
+                    regex::escape(&post.name)
+                ),
+                to: post.link.clone(),
+            });
+        }
+    }
+    // WordPress's category slugs look like ours did, so `C`, `C++` and `C#` all want
+    // `category/c/`. The tag that's spelled just like that gets it, otherwise the first one does.
+    let mut categories: BTreeMap<String, &str> = BTreeMap::new();
+    let tags: BTreeSet<&str> = posts
+        .iter()
+        .filter(|post| !post.draft)
+        .flat_map(|post| post.tags.iter())
+        .map(|tag| tag.as_str())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    for tag in tags {
+        let slug = slugify(tag);
+        if slug.is_empty() {
+            continue;
+        }
+        let plain = slug == tag;
+        categories
+            .entry(slug)
+            .and_modify(|t| {
+                if plain {
+                    *t = tag
+                }
+            })
+            .or_insert(tag);
+    }
+    for (slug, tag) in categories {
+        redirects.push(Redirect {
+            path: format!(
+                "^(archives/p/)?category/{}/?(feed)?/?$",
+                regex::escape(&slug)
+            ),
+            to: format!("{}/{}/$2", settings.url, tag_path(tag)),
+        });
+    }
+    redirects
+}
+
+/// Can we send `/<name>/` to the post? Not if it's a number (that's a post number, or a year),
+/// has spaces, or is something else we put there (`tag`, `archives`, `imgs`...).
+fn usable_name(settings: &Settings, name: &str) -> bool {
+    let pages = ["index", "search"].map(|page| format!("{}.{}", page, settings.extension));
+    !name.is_empty()
+        && !name.chars().all(|c| c.is_ascii_digit())
+        && !name.chars().any(|c| c.is_whitespace() || c == '"')
+        && !RESERVED.contains(&name)
+        && name != settings.short_dir
+        && name != settings.atom
+        && !pages.iter().any(|page| page == name)
+}
+
+/// The path part of a URL, with a `/` on each end.
+pub fn base_path(url: &str) -> String {
+    let after_host = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let path = after_host
+        .find('/')
+        .map(|i| &after_host[i..])
+        .unwrap_or_default()
+        .trim_matches('/');
+    if path.is_empty() {
+        "/".to_owned()
+    } else {
+        format!("/{}/", path)
+    }
+}
+
+fn write_htaccess(
+    settings: &Settings,
+    redirects: &[Redirect],
+    mut file: impl Write,
+) -> Result<(), PublishError> {
+    writeln!(
+        file,
+        "# Written by `publish`, so changes here will get lost. Use `htaccess_extra` in the"
+    )?;
+    writeln!(file, "# config file for anything else you need in here.")?;
+    writeln!(file, "RewriteEngine On")?;
+    writeln!(file, "RewriteBase {}", base_path(&settings.url))?;
+    let extra = settings.htaccess_extra.trim();
+    if !extra.is_empty() {
+        // A `RewriteCond` goes with the next `RewriteRule`, which would be one of ours.
+        let last = extra
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        if last.to_lowercase().starts_with("rewritecond") {
+            warn!(
+                "🔀 htaccess_extra ends with {:?}, which would only apply to the first old link \
+                 rule. Finish it with a RewriteRule.",
+                last
+            );
+        }
+        writeln!(file)?;
+        writeln!(file, "{}", extra)?;
+    }
+    writeln!(file)?;
+    writeln!(file, "# Old links")?;
+    writeln!(file, "RewriteCond %{{QUERY_STRING}} (^|&)p=0*([0-9]+)(&|$)")?;
+    // The `?` drops the old query.
+    writeln!(
+        file,
+        "RewriteRule ^(index\\.php)?$ {}? [R=301,L]",
+        post_url(settings, "%2")
+    )?;
+    for redirect in redirects {
+        writeln!(
+            file,
+            "RewriteRule {} {} [R=301,L]",
+            redirect.path, redirect.to
+        )?;
+    }
+    if settings.includes == Includes::Php {
+        writeln!(file)?;
+        writeln!(file, "# Posts, by number")?;
+        writeln!(
+            file,
+            "RewriteRule ^0*([0-9]+)$ $1.{} [L]",
+            settings.extension
+        )?;
+    }
+    Ok(())
+}
+
+fn write_nginx(
+    settings: &Settings,
+    redirects: &[Redirect],
+    mut file: impl Write,
+) -> Result<(), PublishError> {
+    let base = base_path(&settings.url);
+    writeln!(
+        file,
+        "# Written by `publish`. Include this in the blog's `server` block."
+    )?;
+    writeln!(file)?;
+    writeln!(file, "# Old links")?;
+    // `if` can only check the one thing, so the rewrite checks the path. (The rewrite has its own
+    // groups, so the post number needs a name.)
+    writeln!(
+        file,
+        "if ($arg_p ~ \"^0*(?<post>[0-9]+)$\") {{ rewrite \"^{}(index\\.php)?$\" {}? permanent; }}",
+        base,
+        post_url(settings, "$post")
+    )?;
+    for redirect in redirects {
+        let path = format!("^{}{}", base, redirect.path.trim_start_matches('^'));
+        writeln!(file, "rewrite \"{}\" {} permanent;", path, redirect.to)?;
+    }
+    if settings.includes == Includes::Php {
+        writeln!(file)?;
+        writeln!(file, "# Posts, by number")?;
+        writeln!(
+            file,
+            "rewrite \"^{}0*([0-9]+)$\" {}$1.{} last;",
+            base, base, settings.extension
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(num: u64, name: &str, tags: &[&str]) -> Post {
+        Post {
+            num,
+            name: name.to_owned(),
+            link: format!("https://example.com/blog/{num}"),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Post::default()
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            url: "https://example.com/blog".to_owned(),
+            ..Settings::default()
+        }
+    }
+
+    fn rules(settings: &Settings, posts: &[Post]) -> Vec<(String, String)> {
+        redirects(settings, posts)
+            .into_iter()
+            .map(|r| (r.path, r.to))
+            .collect()
+    }
+
+    #[test]
+    fn names() {
+        let settings = settings();
+        assert!(usable_name(&settings, "my-post"));
+        assert!(!usable_name(&settings, ""));
+        assert!(!usable_name(&settings, "2008"));
+        assert!(!usable_name(&settings, "two words"));
+        for taken in ["tag", "imgs", "archives", "short", "atom.xml", "search.php"] {
+            assert!(!usable_name(&settings, taken), "{}", taken);
+        }
+    }
+
+    #[test]
+    fn rules_for_the_posts_we_have() {
+        let settings = settings();
+        let mut draft = post(4, "secret", &["rust"]);
+        draft.draft = true;
+        let posts = [
+            post(1, "hello.world", &["C", "rust"]),
+            post(2, "imgs", &["C++", "Rust"]),
+            post(3, "hello.world", &["rust"]),
+            draft,
+        ];
+        let rules = rules(&settings, &posts);
+        let to = |path: &str| {
+            rules
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, to)| to.as_str())
+        };
+        assert_eq!(
+            to("^archives/p/0*([0-9]+)(/.*)?$"),
+            Some("https://example.com/blog/$1")
+        );
+        // The first post with the name gets it, and `imgs` is taken.
+        assert_eq!(
+            to(r"^([0-9]{4}/[0-9]{2}/([0-9]{2}/)?)?hello\.world/?$"),
+            Some("https://example.com/blog/1")
+        );
+        assert_eq!(rules.iter().filter(|(p, _)| p.contains("hello")).count(), 1);
+        assert!(!rules
+            .iter()
+            .any(|(p, _)| p.contains("imgs") || p.contains("secret")));
+        // `C` gets `category/c/` over `C++`, and `rust` wins over `Rust`.
+        assert_eq!(
+            to("^(archives/p/)?category/c/?(feed)?/?$"),
+            Some("https://example.com/blog/tag/C/$2")
+        );
+        assert_eq!(
+            to("^(archives/p/)?category/rust/?(feed)?/?$"),
+            Some("https://example.com/blog/tag/rust/$2")
+        );
+    }
+
+    #[test]
+    fn htaccess() {
+        let mut settings = settings();
+        let posts = [post(1, "hello", &[])];
+        let mut out = Vec::new();
+        write_htaccess(&settings, &redirects(&settings, &posts), &mut out).unwrap();
+        let htaccess = String::from_utf8(out).unwrap();
+        assert!(htaccess.contains("RewriteBase /blog/\n"));
+        assert!(htaccess
+            .contains("RewriteRule ^(index\\.php)?$ https://example.com/blog/%2? [R=301,L]"));
+        assert!(htaccess.ends_with("RewriteRule ^0*([0-9]+)$ $1.php [L]\n"));
+
+        settings.includes = Includes::Inline;
+        settings.extension = "html".to_owned();
+        let mut out = Vec::new();
+        write_nginx(&settings, &redirects(&settings, &posts), &mut out).unwrap();
+        let nginx = String::from_utf8(out).unwrap();
+        assert!(nginx.contains(
+            "rewrite \"^/blog/0*([0-9]+)_[^/]*$\" https://example.com/blog/$1.html permanent;"
+        ));
+        assert!(!nginx.contains("Posts, by number"));
+    }
+
+    #[test]
+    fn base_paths() {
+        assert_eq!(base_path("https://example.com"), "/");
+        assert_eq!(base_path("https://example.com/"), "/");
+        assert_eq!(base_path("https://example.com/b/log/"), "/b/log/");
+    }
+}
@@ -0,0 +1,133 @@
+//! A search index, since there's no search on the server.
+//!
+//! `search.json` in the output directory lists the posts (link, title, date, tags and summary)
+//! and, for every word, which posts use it and how much. A word in the title counts for more
+//! than one in the tags, which counts for more than one in the summary or the body. If the
+//! templates have a `search.php`, it gets rendered to `search.<extension>` to do the looking
+//! up in the browser.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use serde_derive::Serialize;
+use tera::{Context, Tera};
+
+use crate::{blog_atom_url, finish_page, Includes, Post, PublishError, Settings};
+
+/// The index, in the output directory.
+pub const SEARCH_INDEX: &str = "search.json";
+/// The (optional) template for the search page.
+const SEARCH_TEMPLATE: &str = "search.php";
+/// How much a word counts for, depending on where it is. (The body is just the word count.)
+const TITLE_WEIGHT: u32 = 10;
+const TAG_WEIGHT: u32 = 5;
+const SUMMARY_WEIGHT: u32 = 3;
+/// Words too common to be worth looking for.
+const STOP_WORDS: &[&str] = &[
+    "about", "after", "again", "all", "also", "and", "any", "are", "because", "been", "but", "can",
+    "could", "did", "does", "doing", "don't", "for", "from", "get", "got", "had", "has", "have",
+    "her", "here", "his", "how", "into", "it's", "its", "just", "like", "more", "not", "now",
+    "one", "only", "other", "our", "out", "really", "she", "should", "some", "than", "that", "the",
+    "their", "them", "then", "there", "they", "this", "was", "what", "when", "which", "who",
+    "will", "with", "would", "you", "your",
+];
+
+/// What the search page needs to show a post.
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    link: &'a str,
+    title: &'a str,
+    date: u64,
+    tags: &'a [String],
+    summary: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Index<'a> {
+    posts: Vec<Entry<'a>>,
+    /// So the page can skip the same words we did.
+    stop_words: &'static [&'static str],
+    /// Each word, with the posts (as positions in `posts`) that use it, and how much.
+    words: BTreeMap<String, Vec<(usize, u32)>>,
+}
+
+/// Split `text` into lowercase words, leaving out anything too short or too common to look for.
+/// (The search page does the same to what you type in, so keep the two in sync.)
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '\''))
+        .map(|word| word.trim_matches('\'').to_lowercase())
+        .filter(|word| {
+            word.chars().count() >= 3
+                && !word.chars().all(|c| c.is_numeric())
+                && !STOP_WORDS.contains(&word.as_str())
+        })
+}
+
+/// Count up the words in a post's text.
+pub fn terms(text: &str) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    for word in words(text) {
+        *terms.entry(word).or_default() += 1;
+    }
+    terms
+}
+
+/// Write the index for `posts` (and the search page, if there's a template for it).
+pub fn update_search(settings: &Settings, posts: &[Post], tera: &Tera) -> Result<(), PublishError> {
+    let output = Path::new(&settings.output);
+    let mut index = Index {
+        posts: Vec::with_capacity(posts.len()),
+        stop_words: STOP_WORDS,
+        words: BTreeMap::new(),
+    };
+    for (i, post) in posts.iter().enumerate() {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for (term, count) in &post.terms {
+            *counts.entry(term.clone()).or_default() += count;
+        }
+        let fields = [
+            (post.title.as_str(), TITLE_WEIGHT),
+            (&post.tags.join(" "), TAG_WEIGHT),
+            (post.summary.as_deref().unwrap_or_default(), SUMMARY_WEIGHT),
+        ];
+        for (text, weight) in fields {
+            for word in words(text) {
+                *counts.entry(word).or_default() += weight;
+            }
+        }
+        for (word, count) in counts {
+            index.words.entry(word).or_default().push((i, count));
+        }
+        index.posts.push(Entry {
+            link: &post.link,
+            title: &post.title,
+            date: post.date,
+            tags: &post.tags,
+            summary: post.summary.as_deref(),
+        });
+    }
+    let path = output.join(SEARCH_INDEX);
+    info!("🔎 Writing {:?} ({} words)", &path, index.words.len());
+    fs::write(path, serde_json::to_string(&index)?)?;
+
+    if !tera
+        .get_template_names()
+        .any(|name| name == SEARCH_TEMPLATE)
+    {
+        debug!("🔎 No {} template, so no search page", SEARCH_TEMPLATE);
+        return Ok(());
+    }
+    let mut context = Context::new();
+    context.insert("url", &settings.url);
+    context.insert("blog_atom_url", &blog_atom_url(settings));
+    context.insert("php", &(settings.includes == Includes::Php));
+    context.insert("index_url", &format!("{}/{}", settings.url, SEARCH_INDEX));
+    let page_name = format!("search.{}", settings.extension);
+    context.insert("search_url", &format!("{}/{}", settings.url, page_name));
+    let page = finish_page(settings, tera.render(SEARCH_TEMPLATE, &context)?)?;
+    info!("🔎 Writing the search page");
+    fs::write(output.join(page_name), page)?;
+    Ok(())
+}
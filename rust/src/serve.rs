@@ -0,0 +1,306 @@
+//! `publish serve`: build the blog into a temporary directory, serve it locally, and rebuild
+//! (and reload any open browser tabs) when the source or templates change.
+//!
+//! This is a preview, not a web server. It only handles `GET`, doesn't do any PHP, and falls
+//! back to the current directory for the static bits the templates point at (`style.css`,
+//! `imgs/` and the like), but nothing else in there.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tera::Tera;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    signal::unix::{signal, SignalKind},
+    sync::broadcast,
+};
+
+use crate::{draft_dir, publish, template_dir, Includes, PublishError, Settings};
+
+/// The URL the browser listens on for reload events.
+const RELOAD_PATH: &str = "/__reload";
+/// How often to look for changes.
+const POLL: Duration = Duration::from_millis(500);
+
+/// Injected into every HTML page so it reloads when we rebuild.
+const RELOAD_SCRIPT: &str = r#"<script>
+new EventSource("/__reload").onmessage = () => location.reload();
+</script>"#;
+
+/// What can be served out of the current directory, if the preview doesn't have it.
+const STATIC_EXTENSIONS: [&str; 12] = [
+    "css", "js", "png", "jpg", "jpeg", "gif", "webp", "svg", "ico", "woff", "woff2", "ttf",
+];
+
+/// The directory the preview gets built in. It goes away when this does, however we stop.
+struct Preview(PathBuf);
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        info!("🌐 Cleaning up {:?}", &self.0);
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub async fn serve(settings: &Settings, port: u16) -> Result<(), PublishError> {
+    let preview_dir =
+        Preview(std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple())));
+    let root = preview_dir.0.clone();
+    fs::create_dir_all(&root)?;
+    let preview = Settings {
+        output: root.to_string_lossy().into_owned(),
+        url: format!("http://localhost:{}", port),
+        short_url: Some(format!("http://localhost:{}", port)),
+        // There's no PHP here, so paste the includes in.
+        includes: Includes::Inline,
+        rebuild_all: true,
+        ..settings.clone()
+    };
+    build(&preview).await?;
+
+    let (reload, _) = broadcast::channel(16);
+    tokio::spawn(watch(preview.clone(), reload.clone()));
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    println!(
+        "Serving {:?} at http://localhost:{}/ (Ctrl-C to stop)",
+        &root, port
+    );
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let root = root.clone();
+                let extension = preview.extension.clone();
+                let reload = reload.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &root, &extension, reload).await {
+                        debug!("🌐 request failed {:?}", e);
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = terminate.recv() => return Ok(()),
+        }
+    }
+}
+
+/// (Re)build the preview. The templates are re-read every time since they may have changed.
+async fn build(settings: &Settings) -> Result<(), PublishError> {
+    let tera = Tera::new(&settings.templates)?;
+    publish(settings, &tera).await
+}
+
+/// Poll the source and template directories, rebuilding when anything changes. The build
+/// cache makes sure only the affected posts get re-rendered.
+async fn watch(settings: Settings, reload: broadcast::Sender<()>) {
+    let mut last = snapshot(&settings);
+    loop {
+        tokio::time::sleep(POLL).await;
+        let current = snapshot(&settings);
+        if current == last {
+            continue;
+        }
+        last = current;
+        info!("👀 Something changed, rebuilding");
+        match build(&settings).await {
+            Ok(()) => {
+                let _ = reload.send(());
+            }
+            Err(e) => error!("👀 Rebuild failed: {:?}", e),
+        }
+    }
+}
+
+/// The modification times of everything we're watching.
+fn snapshot(settings: &Settings) -> BTreeMap<PathBuf, SystemTime> {
+    let mut times = BTreeMap::new();
+    for dir in [
+        PathBuf::from(&settings.source),
+        draft_dir(settings),
+        template_dir(settings),
+    ] {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                times.insert(entry.path(), modified);
+            }
+        }
+    }
+    times
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    root: &Path,
+    extension: &str,
+    mut reload: broadcast::Receiver<()>,
+) -> Result<(), PublishError> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request = String::new();
+    reader.read_line(&mut request).await?;
+    // Skip the rest of the headers, we don't care.
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or("/"),
+    );
+    let path = target.split(['?', '#']).next().unwrap_or("/");
+    trace!("🌐 {} {}", method, path);
+    if method != "GET" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"GET only",
+        )
+        .await;
+    }
+    if path == RELOAD_PATH {
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+            )
+            .await?;
+        while reload.recv().await.is_ok() {
+            stream.write_all(b"data: reload\n\n").await?;
+        }
+        return Ok(());
+    }
+    let Some(file) = find(root, extension, path) else {
+        return respond(
+            &mut stream,
+            "404 Not Found",
+            "text/html",
+            b"<h1>Not Found</h1>",
+        )
+        .await;
+    };
+    let content_type = content_type(&file, extension);
+    let mut body = fs::read(&file)?;
+    if content_type == "text/html" {
+        let page = String::from_utf8_lossy(&body);
+        body = match page.rfind("</body>") {
+            Some(at) => format!("{}{}{}", &page[..at], RELOAD_SCRIPT, &page[at..]),
+            None => format!("{}{}", page, RELOAD_SCRIPT),
+        }
+        .into_bytes();
+    }
+    respond(&mut stream, "200 OK", content_type, &body).await
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), PublishError> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    Ok(())
+}
+
+/// Map a request path to a file, looking in the preview first and the current directory second.
+/// Only static assets come from the current directory, since that's also where the config, the
+/// source and who knows what else live.
+fn find(root: &Path, extension: &str, path: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(percent_decode(path.trim_start_matches('/')));
+    // No wandering out of the directory.
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    let candidate = root.join(&relative);
+    if candidate.is_dir() {
+        let index = candidate.join(format!("index.{}", extension));
+        return index.is_file().then_some(index);
+    }
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    is_static(&relative)
+        .then_some(relative)
+        .filter(|path| path.is_file())
+}
+
+/// Is this something like `style.css` or `imgs/cat.png` (and not in a hidden directory)?
+fn is_static(path: &Path) -> bool {
+    let hidden = path
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    let extension = path
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    !hidden && STATIC_EXTENSIONS.contains(&extension.as_str())
+}
+
+pub fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = path
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn content_type(file: &Path, extension: &str) -> &'static str {
+    let name = file
+        .file_name()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default();
+    if name == "feed" {
+        return "application/rss+xml";
+    }
+    match file
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default()
+    {
+        ext if ext == extension => "text/html",
+        "html" | "htm" | "php" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "inc" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
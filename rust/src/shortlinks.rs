@@ -0,0 +1,136 @@
+//! Make the `short_url` links actually go somewhere.
+//!
+//! Every post with a short link gets a little page at `<short_dir>/NNNN/index.html` in the
+//! output directory that sends you on to the post (with a `meta` refresh, and a canonical link
+//! so search engines know which one is the real one). If the `short_url` has a path (like
+//! `https://jrconl.in/b/`), the pages go under that too (`<short_dir>/b/NNNN/index.html`), so
+//! `short_dir` is the top of the short link host. Point the host at that directory and you're
+//! done. With `short_redirects = true` there's a `_redirects` file in there as well, for hosts
+//! that can do proper redirects from one (Netlify, Cloudflare Pages...).
+//!
+//! Pages for posts that have gone away get cleaned up.
+use std::{collections::BTreeSet, fs, path::Path};
+
+use crate::{highlight::html_escape, rewrites::base_path, Post, PublishError, Settings};
+
+/// The redirects file, in the format the static hosts like.
+pub const REDIRECTS: &str = "_redirects";
+
+pub fn update_short_links(settings: &Settings, posts: &[Post]) -> Result<(), PublishError> {
+    let Some(short_url) = &settings.short_url else {
+        return Ok(());
+    };
+    // The host sees the whole path, so the pages and the redirects need it too.
+    let base = base_path(short_url);
+    let dir = Path::new(&settings.output).join(&settings.short_dir);
+    let pages = dir.join(base.trim_matches('/'));
+    fs::create_dir_all(&pages)?;
+    let mut written = BTreeSet::new();
+    let mut redirects = String::new();
+    for post in posts.iter().filter(|post| post.shortlink.is_some()) {
+        let name = format!("{:04}", post.num);
+        let page_dir = pages.join(&name);
+        let page = page_dir.join("index.html");
+        let content = stub(post);
+        // Don't touch the ones that haven't changed, there could be a lot of them.
+        if fs::read_to_string(&page).ok().as_deref() != Some(content.as_str()) {
+            trace!("🩳 Writing {:?}", &page);
+            fs::create_dir_all(&page_dir)?;
+            fs::write(&page, content)?;
+        }
+        redirects.push_str(&format!("{}{} {} 301\n", base, name, post.link));
+        written.insert(name);
+    }
+    info!("🩳 {} short link(s) in {:?}", written.len(), &pages);
+
+    // Only clean up what looks like one of ours.
+    for entry in fs::read_dir(&pages)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|v| v.to_str()) else {
+            continue;
+        };
+        if path.is_dir()
+            && !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_digit())
+            && !written.contains(name)
+        {
+            info!("🩳 Removing old short link {:?}", &path);
+            let _ = fs::remove_file(path.join("index.html"));
+            let _ = fs::remove_dir(&path);
+        }
+    }
+
+    let redirects_file = dir.join(REDIRECTS);
+    if settings.short_redirects {
+        trace!("🩳 Writing {:?}", &redirects_file);
+        fs::write(redirects_file, redirects)?;
+    } else if redirects_file.exists() {
+        fs::remove_file(redirects_file)?;
+    }
+    Ok(())
+}
+
+/// The page that sends you on to the post.
+fn stub(post: &Post) -> String {
+    let link = html_escape(&post.link);
+    let title = html_escape(&post.title);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>{title}</title>
+    <link rel="canonical" href="{link}" />
+    <meta http-equiv="refresh" content="0; url={link}" />
+    <meta name="robots" content="noindex" />
+  </head>
+  <body>
+    <p><a href="{link}">{title}</a> is over here.</p>
+  </body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_and_redirects_agree() {
+        let output =
+            std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple()));
+        let mut settings = Settings {
+            url: "https://example.com".to_owned(),
+            short_url: Some("https://ex.am/b/".to_owned()),
+            short_redirects: true,
+            output: output.to_string_lossy().into_owned(),
+            ..Settings::default()
+        };
+        let posts: Vec<Post> = [12, 345]
+            .into_iter()
+            .map(|num| Post {
+                num,
+                link: format!("https://example.com/{num}"),
+                shortlink: Some(format!("https://ex.am/b/{num:04}")),
+                ..Post::default()
+            })
+            .collect();
+        update_short_links(&settings, &posts).unwrap();
+        let short = output.join("short");
+        assert_eq!(
+            fs::read_to_string(short.join(REDIRECTS)).unwrap(),
+            "/b/0012 https://example.com/12 301\n/b/0345 https://example.com/345 301\n"
+        );
+        assert!(short.join("b/0012/index.html").is_file());
+        assert!(short.join("b/0345/index.html").is_file());
+
+        // Gone posts get cleaned up, and so does the redirects file when it's not wanted.
+        settings.short_redirects = false;
+        update_short_links(&settings, &posts[1..]).unwrap();
+        assert!(!short.join("b/0012").exists());
+        assert!(short.join("b/0345/index.html").is_file());
+        assert!(!short.join(REDIRECTS).exists());
+        fs::remove_dir_all(output).unwrap();
+    }
+}
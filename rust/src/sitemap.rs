@@ -0,0 +1,184 @@
+//! Help the search engines find things, since that's how folks are supposed to search the blog.
+//!
+//! Writes `sitemap.xml` (or, for really big blogs, a sitemap index pointing at `sitemap-N.xml`
+//! files) and a `robots.txt` that points at it.
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat};
+
+use crate::{group_by_tag, tag_path, Post, PublishError, Settings};
+
+/// The most URLs a single sitemap file is allowed to have.
+const MAX_URLS: usize = 50_000;
+
+/// A single `<url>` entry.
+struct Entry {
+    loc: String,
+    lastmod: u64,
+}
+
+/// Write the sitemap for every post (and tag page) in `posts`.
+pub fn update_sitemap(settings: &Settings, posts: &[Post]) -> Result<(), PublishError> {
+    let output = Path::new(&settings.output);
+    let mut entries = Vec::new();
+    if let Some(newest) = posts.iter().map(|p| p.date).max() {
+        entries.push(Entry {
+            loc: format!("{}/", settings.url),
+            lastmod: newest,
+        });
+    }
+    entries.extend(posts.iter().map(|post| Entry {
+        loc: post.link.clone(),
+        lastmod: post.date,
+    }));
+    for (tag, tag_posts) in group_by_tag(posts) {
+        entries.push(Entry {
+            loc: format!("{}/{}/", settings.url, tag_path(tag)),
+            lastmod: tag_posts.iter().map(|p| p.date).max().unwrap_or_default(),
+        });
+    }
+
+    // Clear out any old split files, in case we've shrunk.
+    for old in split_files(output)? {
+        fs::remove_file(old)?;
+    }
+    let sitemap = output.join("sitemap.xml");
+    if entries.len() <= MAX_URLS {
+        info!("🗺️ Writing {:?} ({} urls)", &sitemap, entries.len());
+        return write_urlset(&sitemap, &entries);
+    }
+    let mut file = fs::File::create(&sitemap)?;
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        file,
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for (i, chunk) in entries.chunks(MAX_URLS).enumerate() {
+        let name = format!("sitemap-{}.xml", i + 1);
+        info!("🗺️ Writing {:?} ({} urls)", output.join(&name), chunk.len());
+        write_urlset(&output.join(&name), chunk)?;
+        writeln!(file, "<sitemap>")?;
+        writeln!(file, "  <loc>{}/{}</loc>", escape(&settings.url), name)?;
+        if let Some(lastmod) = chunk.iter().map(|e| e.lastmod).max() {
+            writeln!(file, "  <lastmod>{}</lastmod>", w3c_date(lastmod))?;
+        }
+        writeln!(file, "</sitemap>")?;
+    }
+    writeln!(file, "</sitemapindex>")?;
+    Ok(())
+}
+
+/// Write `robots.txt`, using the rules from the settings and pointing at the sitemap.
+pub fn update_robots(settings: &Settings) -> Result<(), PublishError> {
+    let robots = Path::new(&settings.output).join("robots.txt");
+    info!("🤖 Writing {:?}", &robots);
+    let mut file = fs::File::create(&robots)?;
+    writeln!(file, "{}", settings.robots.trim_end())?;
+    writeln!(file)?;
+    writeln!(file, "Sitemap: {}/sitemap.xml", settings.url)?;
+    Ok(())
+}
+
+fn write_urlset(path: &Path, entries: &[Entry]) -> Result<(), PublishError> {
+    let mut file = fs::File::create(path)?;
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        file,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for entry in entries {
+        writeln!(file, "<url>")?;
+        writeln!(file, "  <loc>{}</loc>", escape(&entry.loc))?;
+        writeln!(file, "  <lastmod>{}</lastmod>", w3c_date(entry.lastmod))?;
+        writeln!(file, "</url>")?;
+    }
+    writeln!(file, "</urlset>")?;
+    Ok(())
+}
+
+/// Find any `sitemap-N.xml` files in the output directory.
+fn split_files(output: &Path) -> Result<Vec<PathBuf>, PublishError> {
+    let mut found = Vec::new();
+    if !output.exists() {
+        return Ok(found);
+    }
+    for entry in fs::read_dir(output)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+        if let Some(num) = name
+            .strip_prefix("sitemap-")
+            .and_then(|v| v.strip_suffix(".xml"))
+        {
+            if num.parse::<u32>().is_ok() {
+                found.push(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Sitemaps want W3C Datetime, which RFC 3339 happily is.
+fn w3c_date(secs: u64) -> String {
+    DateTime::from_timestamp(secs as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posts(count: usize) -> Vec<Post> {
+        (1..=count)
+            .map(|num| Post {
+                link: format!("https://example.com/{num}"),
+                date: 1_729_522_800 + num as u64,
+                ..Post::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn big_blogs_get_split() {
+        let output =
+            std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&output).unwrap();
+        let settings = Settings {
+            url: "https://example.com".to_owned(),
+            output: output.to_string_lossy().into_owned(),
+            ..Settings::default()
+        };
+        // The index page makes it one too many.
+        update_sitemap(&settings, &posts(MAX_URLS)).unwrap();
+        let index = fs::read_to_string(output.join("sitemap.xml")).unwrap();
+        assert!(index.contains("<sitemapindex"));
+        assert!(index.contains("<loc>https://example.com/sitemap-2.xml</loc>"));
+        assert_eq!(split_files(&output).unwrap().len(), 2);
+        let second = fs::read_to_string(output.join("sitemap-2.xml")).unwrap();
+        assert_eq!(second.matches("<url>").count(), 1);
+        assert!(second.contains(&format!("<loc>https://example.com/{MAX_URLS}</loc>")));
+
+        // And put back together when it shrinks.
+        update_sitemap(&settings, &posts(3)).unwrap();
+        let sitemap = fs::read_to_string(output.join("sitemap.xml")).unwrap();
+        assert!(sitemap.contains("<urlset"));
+        assert_eq!(sitemap.matches("<url>").count(), 4);
+        assert!(split_files(&output).unwrap().is_empty());
+        fs::remove_dir_all(output).unwrap();
+    }
+}
@@ -1,15 +1,7 @@
-<?php
-  $category = filter_input(INPUT_GET, "category", FILTER_SANITIZE_STRING, FILTER_FLAG_STRIP_HIGH | FILTER_FLAG_STRIP_LOW | FILTER_FLAG_STRIP_BACKTICK).strtolower();
-  $cat_file = "archive/".$category.".inc";
-  if !file_exists($cat_file) {
-    $category = "crap";
-    $cat_file = "archive/crap.inc";
-  }
-?>
 <!DOCTYPE html>
 <html xmlns="https://www.w3.org/1999/xhtml" prefix="og: https://ogp.me/ns#">
   <head profile="https://gmpg.org/xfn/1">
-    <title>jr conlin&#039;s ink stained banana &raquo; <? print($category) ?></title>
+    <title>jr conlin&#039;s ink stained banana &raquo; {{ tag }}</title>
     <!-- Pooh got his head stuck in a honeypot, what a project that must have been, to get his head free from the spider that traps spammers -->
     <meta name="viewport" content="initial-scale=1,width=device-width" />
     <meta property="og:type" content="blog" />
@@ -20,10 +12,10 @@
     <meta name="fediverse:creator" content="@jrconlin@jrconlin.com" />
     <meta name="medium" content="blog" />
     <meta http-equiv="Content-Type" content="text/html; charset=UTF-8" />
-    <meta name="title" content="{{ post.title }}" />
-    <meta name="DC.title" content="{{ post.title }}" />
-    <meta property="og:title" content="{{ post.title }}" />
-    <meta property="og:description" content="{{ post.summary }}" />
+    <meta name="title" content="{{ tag }}" />
+    <meta name="DC.title" content="{{ tag }}" />
+    <meta property="og:title" content="{{ tag }}" />
+    <meta property="og:description" content="Everything posted about {{ tag }}" />
     <meta name="ICBM" content="37.309531,-121.984823" />
     <meta
       name="keywords"
@@ -31,14 +23,19 @@
     />
     <link rel="ICON" type="image/png" href="https://jrconlin.com/favicon.png" />
     <link
-      rel="RSS"
+      rel="alternate"
+      type="application/rss+xml"
+      title="Ink-Stained Banana &raquo; {{ tag }} (RSS 2.0)"
+      href="{{ feed_url }}"
+    />
+    <link
+      rel="alternate"
       type="application/atom+xml"
-      title="Ink-Stained Banana (Atom 1.0)"
-      href="https://blog.unitedheroes.net/feed"
+      title="Ink-Stained Banana &raquo; {{ tag }} (Atom 1.0)"
+      href="{{ atom_url }}"
     />
     <link rel="author" href="https://unitedheroes.net/authors/jr.php" />
-    <link rel="shortlink" href="{{ post.shortlink }}" />
-    <link rel="canonical" href="{{ post.link }}" />
+    <link rel="canonical" href="{{ url }}/{{ path }}/" />
     <link
       href="https://fonts.googleapis.com/css?family=Special+Elite&v2"
       rel="stylesheet"
@@ -47,8 +44,9 @@
     <link
       rel="stylesheet"
       type="text/css"
-      href="style.css"
+      href="{{ url }}/style.css"
     />
+    {% if php %}
     <?php
       $holiday = "/holiday/".date("md").".css";
       if (file_exists(".".$holiday)) {
@@ -57,6 +55,7 @@
       <?php
       }
       ?>
+    {% endif %}
   </head>
   <body>
     <div class="topbanner">
@@ -79,9 +78,15 @@
     </div>
     <div class="entryWrap">
       <div class="blogEntry">
-      <h1><?php print($category); ?>
+      <h1>{{ tag }}</h1>
     <ul class="categories">
-      <? include($cat_file); ?>
+      {% for post in posts %}
+      <li>
+        <a href="{{ post.link }}">{{ post.title }}</a>
+        <span class="storyDate">{{ post.date | date(format="%Y-%m-%d") }}</span>
+        {% if post.summary %}<div class="summary">{{ post.summary }}</div>{% endif %}
+      </li>
+      {% endfor %}
     </ul>
     </div>
 
@@ -115,7 +120,7 @@
       <center>
         <div class="rssLinks" id="rssLinks">
           <a
-            href="{{root}}/cdf"
+            href="{{ url }}/cdf"
             class="feed"
             title="Subscribe using the original Syndication Format, CDF"
             ><img
@@ -127,7 +132,7 @@
             CDF</a
           >
           <a
-            href="{{url}}/atom.xml"
+            href="{{ blog_atom_url }}"
             class="feed"
             title="Subscribe to this blog with Atom 1.0 and impress people at parties"
             ><img
@@ -138,6 +143,18 @@
             />
             Atom 1.0</a
           >
+          <a
+            href="{{ atom_url }}"
+            class="feed"
+            title="Subscribe to just {{ tag }} with Atom 1.0"
+            ><img
+              src="https://blog.unitedheroes.net/feedicon.gif"
+              width="16"
+              height="16"
+              style="border: 0"
+            />
+            {{ tag }} only</a
+          >
         </div>
       </center>
     </div>
@@ -145,7 +162,7 @@
       <div class="posts">
         <b>recent posts</b>
         <div class="archives">
-          <?php include("archive.inc"); ?>
+          <?php include("archive/archive.inc"); ?>
         </div>
       </div>
       <hr />
@@ -211,9 +228,11 @@
         });
       }
     </script>
+    {% if php %}
     <div style="margin: 0 200px 0 100px">
       <script type="text/javascript" src="https://blog.unitedheroes.net/holiday/<?= date("md") ?>.js">
       </script>
     </div>
+    {% endif %}
   </body>
 </html>
@@ -23,10 +23,16 @@
     />
     <link rel="ICON" type="image/png" href="https://jrconlin.com/favicon.png" />
     <link
-      rel="RSS"
+      rel="alternate"
+      type="application/rss+xml"
+      title="Ink-Stained Banana (RSS 2.0)"
+      href="{{ url }}/feed"
+    />
+    <link
+      rel="alternate"
       type="application/atom+xml"
       title="Ink-Stained Banana (Atom 1.0)"
-      href="https://blog.unitedheroes.net/feed"
+      href="{{ blog_atom_url }}"
     />
     <link rel="author" href="https://unitedheroes.net/authors/jr.php" />
     <link rel="shortlink" href="{{ post.shortlink }}" />
@@ -39,8 +45,12 @@
     <link
       rel="stylesheet"
       type="text/css"
-      href="style.css"
+      href="{{ url }}/style.css"
     />
+    {% if highlight_css %}
+    <link rel="stylesheet" type="text/css" href="{{ highlight_css }}" />
+    {% endif %}
+    {% if php %}
     <?php
       $holiday = "/holiday/".date("md").".css";
       if (file_exists(".".$holiday)) {
@@ -49,6 +59,7 @@
       <?php
       }
       ?>
+    {% endif %}
   </head>
   <body>
     <div class="hide" style="border: 2px solid red; padding: 2em">
@@ -90,12 +101,13 @@
             title="Permanent Link: {{ post.title }}"
             ><span class="storyDate">{{ post.date | date(format="%Y-%m-%d %H:%M:%S") }}</span></a>
           :: {{post.title}}
+          {% if post.reading_time %}<span class="storyReadingTime">({{ post.reading_time }} min read)</span>{% endif %}
         </h2>
         <div class="storyCategory">
           <ul class="post-categories">
-            {% for tag in post.tags %}
+            {% for tag in tags %}
             <li>
-              <a href="https://blog.unitedheroes.net/category/{{tag}}" rel="category tag">{{ tag }}</a>
+              <a href="{{ url }}/{{ tag.path }}/" rel="category tag">{{ tag.name }}</a>
             </li>
             {% endfor %}
           </ul>
@@ -103,6 +115,16 @@
       <div class="post">
         <div class="storyContent">{{ post.body }}</div>
       </div>
+      {% if post.related %}
+      <div class="related">
+        <h3>You might also like</h3>
+        <ul>
+          {% for other in post.related %}
+          <li><a href="{{ other.link }}">{{ other.title }}</a></li>
+          {% endfor %}
+        </ul>
+      </div>
+      {% endif %}
       <div class="shortlink">
         <label>Short Link:</label><span class="link">{{ post.shortlink }}</span><span class="clip">➡️📋</span>
       </div>
@@ -145,7 +167,7 @@
             CDF</a
           >
           <a
-            href="https://blog.unitedheroes.net//feed"
+            href="{{ blog_atom_url }}"
             class="feed"
             title="Subscribe to this blog with Atom 1.0 and impress people at parties"
             ><img
@@ -165,6 +187,7 @@
         <div class="archives">
           <?php include("archive/archive.inc"); ?>
         </div>
+        <a href="{{ url }}/archives/">older posts</a>
       </div>
       <hr />
       <div id="group">
@@ -219,9 +242,11 @@
         });
       }
     </script>
+    {% if php %}
     <div style="margin: 0 200px 0 100px">
       <script type="text/javascript" src="https://blog.unitedheroes.net/holiday/<?= date("md") ?>.js">
       </script>
     </div>
+    {% endif %}
   </body>
 </html>